@@ -0,0 +1,89 @@
+//! Measures a successful `create_invoice` round trip, from the HTTP request to the invoice coming
+//! back from a fake NWC wallet service, over a relay on loopback.
+//!
+//! Run with `cargo bench --bench create_invoice`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use arc_swap::ArcSwap;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use common::{FakeWallet, start_relay};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use futures::future::join_all;
+use std::sync::Arc;
+use thor::config::{Config, ConfigFormat};
+use thor::http_server::{AppState, router};
+use thor::invoice_creator::BackendInvoiceCreatorFactory;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+const BACKENDS_PER_USER: usize = 2;
+
+/// Every user gets `BACKENDS_PER_USER` NWC backends, all connecting to the same wallet.
+fn app(relay_url: &str, wallet: &FakeWallet, users: usize) -> Router {
+    let mut config = r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:0"
+log_dir = "/tmp/thor"
+"#
+    .to_string();
+    for i in 0..users {
+        let nwcs: Vec<String> = (0..BACKENDS_PER_USER)
+            .map(|_| wallet.uri(relay_url).unwrap())
+            .collect();
+        config.push_str(&format!(
+            "\n[[users]]\nname = \"user{}\"\nnwcs = [{}]\n",
+            i,
+            nwcs.join(", ")
+        ));
+    }
+
+    let config = Config::parse(&config, ConfigFormat::Toml).unwrap();
+    let state = AppState::new(&config, Arc::new(BackendInvoiceCreatorFactory)).unwrap();
+    router(Arc::new(ArcSwap::from_pointee(state)))
+}
+
+/// Every user asks for one invoice, concurrently.
+async fn create_invoices(app: &Router, users: usize) {
+    let responses = join_all((0..users).map(|i| {
+        let req = Request::builder()
+            .uri(format!("/lnurlp/user{}?amount=1000", i))
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(req)
+    }))
+    .await;
+    for res in responses {
+        assert_eq!(res.unwrap().status(), StatusCode::OK);
+    }
+}
+
+fn bench_create_invoice(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (_relay, relay_url, wallet) = rt.block_on(async {
+        let relay = start_relay().await.unwrap();
+        let relay_url = relay.url();
+        let wallet = FakeWallet::start(&relay_url, Some("lnbc10n1bench"))
+            .await
+            .unwrap();
+        (relay, relay_url, wallet)
+    });
+
+    let mut group = c.benchmark_group("create_invoice");
+    for users in [1, 4, 8] {
+        let app = app(&relay_url, &wallet, users);
+        // fails fast if the wallet doesn't answer, rather than in the middle of the measurements.
+        rt.block_on(create_invoices(&app, users));
+        group.bench_with_input(BenchmarkId::from_parameter(users), &users, |b, &users| {
+            b.to_async(&rt).iter(|| create_invoices(&app, users));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_create_invoice);
+criterion_main!(benches);
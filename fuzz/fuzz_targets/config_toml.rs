@@ -0,0 +1,17 @@
+//! Feeds arbitrary bytes to the TOML config parser and its validation, which must reject bad
+//! configs with an error rather than panic.
+//!
+//! Run it with a nightly toolchain from the repository root:
+//!
+//!     cargo install cargo-fuzz
+//!     cargo +nightly fuzz run config_toml
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use thor::config::{Config, ConfigFormat};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(contents) = std::str::from_utf8(data) {
+        let _ = Config::parse(contents, ConfigFormat::Toml);
+    }
+});
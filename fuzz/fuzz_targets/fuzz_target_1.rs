@@ -0,0 +1,94 @@
+//! Feeds arbitrary query strings to the LUD-06 callback, `GET /lnurlp/{username}`, so that the
+//! parsing of `amount`, `comment`, `payerData`, `currency` and `nostr` and the checks made on
+//! them before an invoice is created are exercised. Invoices come from a mock backend.
+//!
+//! Run it with a nightly toolchain from the repository root:
+//!
+//!     cargo install cargo-fuzz
+//!     cargo +nightly fuzz run fuzz_target_1
+#![no_main]
+
+use arc_swap::ArcSwap;
+use axum::Router;
+use axum::body::Body;
+use axum::http::Request;
+use libfuzzer_sys::fuzz_target;
+use std::sync::{Arc, OnceLock};
+use thor::config::{Config, ConfigFormat, UserConfig};
+use thor::http_server::{AppState, router};
+use thor::invoice_creator::{InvoiceCreator, InvoiceCreatorFactory, UserInvoiceCreators};
+use tower::ServiceExt;
+
+const CONFIG: &str = r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:0"
+log_dir = "/tmp/thor"
+
+[[users]]
+name = "alice"
+nwcs = ["nwc://unused"]
+comment_allowed = 140
+payer_data = { name = { mandatory = false } }
+"#;
+
+struct MockInvoiceCreator;
+
+#[async_trait::async_trait]
+impl InvoiceCreator for MockInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn check_health(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn create_invoice(
+        &self,
+        _amount_msat: u64,
+        _description: &str,
+        _comment: Option<&str>,
+        _expiry: Option<u64>,
+    ) -> anyhow::Result<String> {
+        Ok("lnbc15n1mockinvoice".to_string())
+    }
+}
+
+struct MockInvoiceCreatorFactory;
+
+impl InvoiceCreatorFactory for MockInvoiceCreatorFactory {
+    fn create_from_config(&self, _user_config: &UserConfig) -> anyhow::Result<UserInvoiceCreators> {
+        Ok(UserInvoiceCreators {
+            primary: vec![Arc::new(MockInvoiceCreator)],
+            fallback: vec![],
+        })
+    }
+}
+
+fn app() -> &'static (tokio::runtime::Runtime, Router) {
+    static APP: OnceLock<(tokio::runtime::Runtime, Router)> = OnceLock::new();
+    APP.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let config = Config::parse(CONFIG, ConfigFormat::Toml).unwrap();
+        let state = AppState::new(&config, Arc::new(MockInvoiceCreatorFactory)).unwrap();
+        (runtime, router(Arc::new(ArcSwap::from_pointee(state))))
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(query) = std::str::from_utf8(data) else {
+        return;
+    };
+    // inputs that don't even make a valid request line never reach thor.
+    let Ok(request) = Request::get(format!("/lnurlp/alice?{}", query)).body(Body::empty()) else {
+        return;
+    };
+    let (runtime, app) = app();
+    let res = runtime.block_on(app.clone().oneshot(request)).unwrap();
+    // the mock backend never fails, so whatever the query, it's either an invoice or a 4xx.
+    assert!(!res.status().is_server_error(), "{}", res.status());
+});
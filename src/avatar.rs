@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+/// A user's picture, shown by wallets through the LUD-06 metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Avatar {
+    /// The metadata entry type, e.g. `image/png;base64`.
+    pub kind: &'static str,
+    pub base64: String,
+}
+
+impl Avatar {
+    /// Reads a PNG or JPEG file, which must not be larger than `max_bytes`.
+    pub fn load(path: &str, max_bytes: u64) -> Result<Avatar> {
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("failed to read avatar {}", path))?
+            .len();
+        if size > max_bytes {
+            anyhow::bail!(
+                "avatar {} is {} bytes, larger than max_avatar_bytes {}",
+                path,
+                size,
+                max_bytes
+            );
+        }
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read avatar {}", path))?;
+        Avatar::from_bytes(&bytes).with_context(|| format!("invalid avatar {}", path))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Avatar> {
+        // LUD-06 only allows PNG and JPEG, told apart by their magic numbers.
+        let kind = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            "image/png;base64"
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            "image/jpeg;base64"
+        } else {
+            anyhow::bail!("only PNG and JPEG are supported");
+        };
+        Ok(Avatar {
+            kind,
+            base64: BASE64.encode(bytes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_image_type() {
+        let png = Avatar::from_bytes(b"\x89PNG\r\n\x1a\nrest").unwrap();
+        assert_eq!(png.kind, "image/png;base64");
+        assert_eq!(BASE64.decode(png.base64).unwrap(), b"\x89PNG\r\n\x1a\nrest");
+
+        let jpeg = Avatar::from_bytes(&[0xff, 0xd8, 0xff, 0xe0]).unwrap();
+        assert_eq!(jpeg.kind, "image/jpeg;base64");
+
+        assert!(Avatar::from_bytes(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn rejects_too_large_file() {
+        let path = std::env::temp_dir().join(format!("thor-avatar-{}.png", std::process::id()));
+        std::fs::write(&path, b"\x89PNG\r\n\x1a\n0123456789").unwrap();
+        let path_str = path.to_str().unwrap();
+        assert!(Avatar::load(path_str, 18).is_ok());
+        assert!(Avatar::load(path_str, 17).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::Result;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde_json::Value;
+
+const PREFIX: &str = "THOR__";
+const SEPARATOR: &str = "__";
+
+/// Applies `THOR__...` environment variables on top of the config loaded from file.
+/// See the doc comment of `Config` for the naming convention.
+pub(super) fn apply_env_overrides(
+    config: &mut Value,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<()> {
+    let mut vars: Vec<_> = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(PREFIX))
+        .collect();
+    // so that `..__NWCS__2` is applied before `..__NWCS__10`.
+    vars.sort_by_cached_key(|(key, _)| {
+        key.split(SEPARATOR)
+            .map(|segment| (segment.parse::<usize>().ok(), segment.to_string()))
+            .collect::<Vec<_>>()
+    });
+    for (key, value) in vars {
+        let path: Vec<String> = key[PREFIX.len()..]
+            .split(SEPARATOR)
+            .map(|segment| segment.to_ascii_lowercase())
+            .collect();
+        if path.iter().any(|segment| segment.is_empty()) {
+            anyhow::bail!("invalid config override {}", key);
+        }
+        set(config, &path, parse_value(&value))
+            .map_err(|e| anyhow::anyhow!("invalid config override {}: {}", key, e))?;
+    }
+    Ok(())
+}
+
+// arrays and tables can be written in JSON. Anything else is a string, even if it looks like a
+// number, e.g. a password of digits. `Lenient` parses it where a number or a boolean is expected.
+fn parse_value(s: &str) -> Value {
+    match serde_json::from_str(s) {
+        Ok(value @ (Value::Array(_) | Value::Object(_))) => value,
+        _ => Value::String(s.to_string()),
+    }
+}
+
+/// Deserializes a config with overrides, like `serde_json::Value` does, except that strings are
+/// also taken as numbers and booleans for the fields of those types.
+pub(super) struct Lenient(pub Value);
+
+macro_rules! parse_string {
+    ($($method:ident => $ty:ty, $visit:ident;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match &self.0 {
+                    Value::String(s) => match s.parse::<$ty>() {
+                        Ok(parsed) => visitor.$visit(parsed),
+                        Err(_) => self.0.deserialize_any(visitor),
+                    },
+                    _ => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Lenient {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(array) => visitor.visit_seq(LenientSeq(array.into_iter())),
+            Value::Object(object) => visitor.visit_map(LenientMap {
+                entries: object.into_iter(),
+                value: None,
+            }),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    parse_string! {
+        deserialize_bool => bool, visit_bool;
+        deserialize_i8 => i64, visit_i64;
+        deserialize_i16 => i64, visit_i64;
+        deserialize_i32 => i64, visit_i64;
+        deserialize_i64 => i64, visit_i64;
+        deserialize_u8 => u64, visit_u64;
+        deserialize_u16 => u64, visit_u64;
+        deserialize_u32 => u64, visit_u64;
+        deserialize_u64 => u64, visit_u64;
+        deserialize_f32 => f64, visit_f64;
+        deserialize_f64 => f64, visit_f64;
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct LenientSeq(std::vec::IntoIter<Value>);
+
+impl<'de> SeqAccess<'de> for LenientSeq {
+    type Error = serde_json::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(Lenient(value)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct LenientMap {
+    entries: serde_json::map::IntoIter,
+    value: Option<Value>,
+}
+
+impl<'de> MapAccess<'de> for LenientMap {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(Lenient(value)),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+fn set(node: &mut Value, path: &[String], value: Value) -> Result<()> {
+    let Some((segment, rest)) = path.split_first() else {
+        *node = value;
+        return Ok(());
+    };
+    if node.is_null() {
+        *node = if segment.parse::<usize>().is_ok() {
+            Value::Array(vec![])
+        } else {
+            Value::Object(Default::default())
+        };
+    }
+    let child = match node {
+        Value::Array(array) => {
+            let index: usize = segment
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} is not an array index", segment))?;
+            if index > array.len() {
+                anyhow::bail!("index {} skips over missing elements", index);
+            }
+            if index == array.len() {
+                array.push(Value::Null);
+            }
+            &mut array[index]
+        }
+        Value::Object(object) => object.entry(segment.clone()).or_insert(Value::Null),
+        _ => anyhow::bail!("{} is not a table or an array", segment),
+    };
+    set(child, rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn overrides_nested_fields() -> Result<()> {
+        let mut config = json!({
+            "server": {"domain": "example.com", "health_check_timeout_ms": 5000},
+            "users": [{"name": "alice", "nwcs": ["nwc://old"]}],
+        });
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("THOR__SERVER__DOMAIN", "thor.dev"),
+                ("THOR__SERVER__HEALTH_CHECK_TIMEOUT_MS", "1000"),
+                ("THOR__USERS__0__NWCS__0", "nwc://new"),
+                ("THOR__USERS__0__NWCS__1", "nwc://another"),
+                ("UNRELATED", "ignored"),
+            ]),
+        )?;
+        assert_eq!(
+            config,
+            json!({
+                "server": {"domain": "thor.dev", "health_check_timeout_ms": "1000"},
+                "users": [{"name": "alice", "nwcs": ["nwc://new", "nwc://another"]}],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn adds_missing_users() -> Result<()> {
+        let mut config = json!({"users": []});
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("THOR__USERS__0__NAME", "bob"),
+                ("THOR__USERS__0__NWCS__0", "nwc://bob"),
+            ]),
+        )?;
+        assert_eq!(
+            config,
+            json!({"users": [{"name": "bob", "nwcs": ["nwc://bob"]}]})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strings_are_parsed_by_field_type() -> Result<()> {
+        #[derive(Debug, serde::Deserialize)]
+        struct Server {
+            password: String,
+            timeout_ms: u64,
+            verbose: bool,
+            ratio: Option<f64>,
+            listen_addrs: Vec<String>,
+        }
+        let mut config = json!({});
+        apply_env_overrides(
+            &mut config,
+            vars(&[
+                ("THOR__PASSWORD", "1234"),
+                ("THOR__TIMEOUT_MS", "1000"),
+                ("THOR__VERBOSE", "true"),
+                ("THOR__RATIO", "0.5"),
+                ("THOR__LISTEN_ADDRS", r#"["127.0.0.1:1", "127.0.0.1:2"]"#),
+            ]),
+        )?;
+        let server = Server::deserialize(Lenient(config))?;
+        assert_eq!(server.password, "1234");
+        assert_eq!(server.timeout_ms, 1000);
+        assert!(server.verbose);
+        assert_eq!(server.ratio, Some(0.5));
+        assert_eq!(server.listen_addrs, ["127.0.0.1:1", "127.0.0.1:2"]);
+
+        let config =
+            json!({"password": "p", "timeout_ms": "soon", "verbose": false, "listen_addrs": []});
+        assert!(Server::deserialize(Lenient(config)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_index_gaps() {
+        let mut config = json!({"users": []});
+        let res = apply_env_overrides(&mut config, vars(&[("THOR__USERS__1__NAME", "bob")]));
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::config::{Config, display_paths};
+use crate::http_server::{AppState, SharedState, UserChange};
+use anyhow::Result;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+
+/// Watches the config files and swaps in a freshly built `AppState` whenever one changes.
+/// `domains` are the states of the other domains, which are reloaded along with the primary one.
+/// Domains added to the file are only served after a restart.
+///
+/// The returned watcher must be kept alive for as long as reloading is wanted.
+pub fn watch_config(
+    config_paths: &[PathBuf],
+    state: SharedState,
+    domains: Vec<SharedState>,
+) -> Result<RecommendedWatcher> {
+    let config_paths = canonical(config_paths)?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.send(res);
+    })?;
+    // Editors usually replace the file instead of writing it in place,
+    // so we watch the parent directory and filter events by path.
+    let watch_dirs: BTreeSet<&Path> = config_paths
+        .iter()
+        .filter_map(|config_path| config_path.parent())
+        .collect();
+    for watch_dir in &watch_dirs {
+        watcher.watch(watch_dir, RecursiveMode::NonRecursive)?;
+    }
+
+    tracing::info!("watching {} for changes", display_paths(&config_paths));
+    tokio::spawn(async move {
+        while let Some(res) = rx.recv().await {
+            let event: notify::Event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::error!(error = %e, "config watcher error.");
+                    continue;
+                }
+            };
+            if !is_relevant(&event, &config_paths) {
+                continue;
+            }
+            reload(&config_paths, &state, &domains);
+        }
+    });
+
+    Ok(watcher)
+}
+
+/// Reloads the config files on SIGHUP, like `watch_config` does when they change. For deployments
+/// where the file is changed in place, or watching it isn't reliable, e.g. on network filesystems.
+pub fn reload_on_sighup(
+    config_paths: &[PathBuf],
+    state: SharedState,
+    domains: Vec<SharedState>,
+) -> Result<()> {
+    let config_paths = canonical(config_paths)?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            tracing::info!("received SIGHUP, reloading configuration.");
+            reload(&config_paths, &state, &domains);
+        }
+    });
+    Ok(())
+}
+
+/// The config paths in their canonical directories, which are also the ones watched, so that
+/// events can be told apart by their full path. The files themselves are left as they are, since
+/// they may be symlinks which are swapped.
+fn canonical(config_paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    config_paths
+        .iter()
+        .map(|config_path| {
+            let config_path = std::path::absolute(config_path)?;
+            let (Some(dir), Some(file_name)) = (config_path.parent(), config_path.file_name())
+            else {
+                anyhow::bail!("{} is not a file", config_path.display());
+            };
+            Ok(dir.canonicalize()?.join(file_name))
+        })
+        .collect()
+}
+
+fn is_relevant(event: &notify::Event, config_paths: &[PathBuf]) -> bool {
+    if event.kind.is_access() {
+        return false;
+    }
+    event
+        .paths
+        .iter()
+        .any(|p| config_paths.iter().any(|config_path| p == config_path))
+}
+
+fn reload(config_paths: &[PathBuf], state: &SharedState, domains: &[SharedState]) {
+    let paths: Vec<&Path> = config_paths.iter().map(PathBuf::as_path).collect();
+    // all states are built before any is swapped in, so that a bad config changes nothing.
+    let new_states = Config::load_from_tomls(&paths).and_then(|config| {
+        let new_state = state.load().reload(&config)?;
+        let new_domains = domains
+            .iter()
+            .map(|domain| domain.load().reload_domain(&config))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((new_state, new_domains))
+    });
+    match new_states {
+        // requests in flight keep the old state, and the backends of removed users with it, until
+        // they complete.
+        Ok((new_state, new_domains)) => {
+            // diffed against the state swapped in here, not whatever a concurrent reload left.
+            let new_state = Arc::new(new_state);
+            log_user_changes(&state.swap(new_state.clone()), &new_state);
+            for (domain, new_domain) in domains.iter().zip(new_domains) {
+                let new_domain = Arc::new(new_domain);
+                log_user_changes(&domain.swap(new_domain.clone()), &new_domain);
+            }
+            tracing::info!(
+                "configuration reloaded from {}",
+                display_paths(config_paths)
+            );
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to reload configuration, keeping the old one.");
+        }
+    }
+}
+
+fn log_user_changes(old: &AppState, new: &AppState) {
+    let domain = new.domain();
+    for change in old.user_changes(new) {
+        match change {
+            UserChange::Added(user) => tracing::info!(domain, user, "user added."),
+            UserChange::Removed(user) => tracing::info!(domain, user, "user removed."),
+            UserChange::BackendsChanged(user) => {
+                tracing::info!(domain, user, "user backends changed.")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_server::AppState;
+    use crate::invoice_creator::BackendInvoiceCreatorFactory;
+    use arc_swap::ArcSwap;
+
+    const VALID_CONFIG: &str = r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:8080"
+log_dir = "/tmp/thor"
+
+[[users]]
+name = "alice"
+nwcs = ["nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c"]
+"#;
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("thor-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn empty_state() -> SharedState {
+        let config: Config = toml::from_str(
+            r#"
+users = []
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:8080"
+log_dir = "/tmp/thor"
+"#,
+        )
+        .unwrap();
+        Arc::new(ArcSwap::from_pointee(
+            AppState::new(&config, Arc::new(BackendInvoiceCreatorFactory)).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn only_events_of_config_files_are_relevant() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let config_paths = vec![dir.join("thor.toml")];
+        let event = |path: PathBuf| {
+            notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any))
+                .add_path(path)
+        };
+        assert!(is_relevant(&event(dir.join("thor.toml")), &config_paths));
+        assert!(!is_relevant(
+            &event(dir.join("other").join("thor.toml")),
+            &config_paths
+        ));
+        assert!(!is_relevant(&event(dir.join("users.toml")), &config_paths));
+    }
+
+    #[test]
+    fn reload_swaps_state_on_valid_config() {
+        let path = write_config("valid", VALID_CONFIG);
+        let state = empty_state();
+        let old = state.load_full();
+
+        reload(std::slice::from_ref(&path), &state, &[]);
+        assert!(!Arc::ptr_eq(&old, &state.load_full()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn user_changes_on_reload() {
+        let state = |users: &[(&str, &str)]| {
+            let mut contents = VALID_CONFIG[..VALID_CONFIG.find("[[users]]").unwrap()].to_string();
+            for (name, relay) in users {
+                let nwc = VALID_CONFIG
+                    .split('"')
+                    .find(|s| s.starts_with("nostr+walletconnect://"))
+                    .unwrap()
+                    .replace("relay.damus.io", relay);
+                contents.push_str(&format!(
+                    "[[users]]\nname = \"{name}\"\nnwcs = [\"{nwc}\"]\n"
+                ));
+            }
+            let config = Config::parse(&contents, crate::config::ConfigFormat::Toml).unwrap();
+            AppState::new(&config, Arc::new(BackendInvoiceCreatorFactory)).unwrap()
+        };
+        let old = state(&[("alice", "relay.damus.io"), ("carol", "relay.damus.io")]);
+        // alice moves to another relay, bob is added and carol removed.
+        let new = state(&[("alice", "nos.lol"), ("bob", "relay.damus.io")]);
+        assert_eq!(
+            old.user_changes(&new),
+            vec![
+                UserChange::BackendsChanged("alice".to_string()),
+                UserChange::Added("bob".to_string()),
+                UserChange::Removed("carol".to_string()),
+            ]
+        );
+        assert!(new.user_changes(&new).is_empty());
+    }
+
+    #[test]
+    fn reload_keeps_old_state_on_invalid_config() {
+        let path = write_config("invalid", "this is not toml");
+        let state = empty_state();
+        let old = state.load_full();
+
+        reload(std::slice::from_ref(&path), &state, &[]);
+        assert!(Arc::ptr_eq(&old, &state.load_full()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_fails_when_a_domain_is_removed() {
+        let with_domain = format!("{}\n[[domains]]\ndomain = \"other.com\"\n", VALID_CONFIG);
+        let config = Config::parse(&with_domain, crate::config::ConfigFormat::Toml).unwrap();
+        let domain: SharedState = Arc::new(ArcSwap::from_pointee(
+            AppState::for_domain(&config, "other.com", Arc::new(BackendInvoiceCreatorFactory))
+                .unwrap(),
+        ));
+        let domains = [domain.clone()];
+        let state = empty_state();
+
+        let path = write_config("domains", &with_domain);
+        let old_domain = domain.load_full();
+        reload(std::slice::from_ref(&path), &state, &domains);
+        assert!(!Arc::ptr_eq(&old_domain, &domain.load_full()));
+
+        // the primary domain is kept as well, nothing is half reloaded.
+        std::fs::write(&path, VALID_CONFIG).unwrap();
+        let (old, old_domain) = (state.load_full(), domain.load_full());
+        reload(std::slice::from_ref(&path), &state, &domains);
+        assert!(Arc::ptr_eq(&old, &state.load_full()));
+        assert!(Arc::ptr_eq(&old_domain, &domain.load_full()));
+        std::fs::remove_file(path).unwrap();
+    }
+}
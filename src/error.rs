@@ -1,13 +1,42 @@
-use axum::http::StatusCode;
+use axum::http::{HeaderValue, StatusCode, header};
 use axum::response::IntoResponse;
 use axum::response::Json;
+use nostr::nips::nip47;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::time::Duration;
+
+/// Machine readable codes sent as `code` along the `reason` of errors, for clients to handle them
+/// without parsing the reason. They never change meaning across releases, see
+/// `doc/error-codes.md`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UserNotFound = 1001,
+    AmountOutOfRange = 1002,
+    BackendUnavailable = 1003,
+    RateLimitExceeded = 1004,
+    CommentTooLong = 1005,
+    InvalidPayerData = 1006,
+    UnsupportedCurrency = 1007,
+    InvalidZapRequest = 1008,
+    AmountMissing = 1009,
+    DailyCapReached = 1010,
+    LinkExpired = 1011,
+    BackendTimeout = 1012,
+    InvoiceNotSupported = 1013,
+    ExchangeRateUnavailable = 1014,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Lud06Error {
     status: String,
     reason: String,
+    // not part of LUD-06 either, see `ErrorCode`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    code: Option<u16>,
+    // not part of LUD-06, lets clients correlate their logs with ours.
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none", default)]
+    request_id: Option<String>,
 }
 
 impl Lud06Error {
@@ -15,8 +44,36 @@ impl Lud06Error {
         Lud06Error {
             status: "ERROR".to_string(),
             reason,
+            code: None,
+            request_id: None,
         }
     }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = Some(code as u16);
+        self
+    }
+
+    pub fn user_not_found(username: &str) -> Self {
+        Lud06Error::new(format!("user {} not found", username)).with_code(ErrorCode::UserNotFound)
+    }
+
+    /// Always `ERROR`, as LUD-06 requires.
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn code(&self) -> Option<u16> {
+        self.code
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
 }
 
 impl Display for Lud06Error {
@@ -28,10 +85,7 @@ impl Display for Lud06Error {
 impl From<anyhow::Error> for Lud06Error {
     fn from(e: anyhow::Error) -> Self {
         tracing::error!("{}", e.backtrace());
-        Lud06Error {
-            status: "ERROR".to_string(),
-            reason: e.to_string(),
-        }
+        Lud06Error::new(e.to_string())
     }
 }
 
@@ -39,11 +93,44 @@ impl From<anyhow::Error> for Lud06Error {
 pub struct HttpError {
     status_code: StatusCode,
     e: Lud06Error,
+    retry_after: Option<Duration>,
 }
 
 impl HttpError {
     pub fn new(status_code: StatusCode, e: Lud06Error) -> HttpError {
-        HttpError { status_code, e }
+        HttpError {
+            status_code,
+            e,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> HttpError {
+        self.e = self.e.with_code(code);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> HttpError {
+        self.e.request_id = Some(request_id);
+        self
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    /// The LUD-06 error sent as the body of the response.
+    pub fn body(&self) -> &Lud06Error {
+        &self.e
+    }
+
+    pub fn too_many_requests(retry_after: Duration) -> HttpError {
+        HttpError {
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+            e: Lud06Error::new("too many requests, please retry later".to_string())
+                .with_code(ErrorCode::RateLimitExceeded),
+            retry_after: Some(retry_after),
+        }
     }
 }
 
@@ -51,12 +138,163 @@ impl IntoResponse for HttpError {
     fn into_response(self) -> axum::response::Response {
         let mut res = Json(self.e).into_response();
         *res.status_mut() = self.status_code;
+        if let Some(retry_after) = self.retry_after {
+            // Retry-After is in whole seconds, round up so that the client won't retry too early.
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
         res
     }
 }
 
+/// Serialized as the body of the response, which clients deserialize back into a [`Lud06Error`].
+impl Serialize for HttpError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.e.serialize(serializer)
+    }
+}
+
 impl From<anyhow::Error> for HttpError {
     fn from(e: anyhow::Error) -> Self {
         HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, e.into())
     }
 }
+
+/// NWC errors are told apart, so that payers know whether retrying can help. The wallet failed,
+/// not thor, hence 502 rather than 500.
+impl From<nwc::Error> for HttpError {
+    fn from(e: nwc::Error) -> Self {
+        let (status_code, code) = match &e {
+            nwc::Error::Timeout => (StatusCode::GATEWAY_TIMEOUT, ErrorCode::BackendTimeout),
+            nwc::Error::NIP47(nip47::Error::ErrorCode(e)) => match e.code {
+                nip47::ErrorCode::RateLimited => {
+                    (StatusCode::TOO_MANY_REQUESTS, ErrorCode::RateLimitExceeded)
+                }
+                nip47::ErrorCode::NotImplemented
+                | nip47::ErrorCode::Restricted
+                | nip47::ErrorCode::Unauthorized => {
+                    (StatusCode::BAD_GATEWAY, ErrorCode::InvoiceNotSupported)
+                }
+                _ => (StatusCode::BAD_GATEWAY, ErrorCode::BackendUnavailable),
+            },
+            nwc::Error::NIP47(_) | nwc::Error::Pool(_) | nwc::Error::PrematureExit => {
+                (StatusCode::BAD_GATEWAY, ErrorCode::BackendUnavailable)
+            }
+        };
+        HttpError::new(status_code, Lud06Error::new(e.to_string()).with_code(code))
+    }
+}
+
+/// Errors caused by invalid parameters sent by the payer.
+#[derive(Debug)]
+pub enum BadRequest {
+    /// LUD-12: the comment is longer than `commentAllowed`.
+    CommentTooLong { max: u64 },
+    /// The amount is outside of `[minSendable, maxSendable]`.
+    AmountOutOfRange { min: u64, max: u64 },
+    /// LUD-18: the payer data is malformed or doesn't match what the user accepts.
+    InvalidPayerData(String),
+    /// LUD-21: the currency is not supported.
+    UnsupportedCurrency(String),
+    /// NIP-57: the zap request is malformed, or the user doesn't accept zaps.
+    InvalidZapRequest(String),
+}
+
+impl Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BadRequest::CommentTooLong { max } => {
+                write!(f, "comment too long, at most {} characters allowed", max)
+            }
+            BadRequest::AmountOutOfRange { min, max } => {
+                write!(f, "amount must be between {} and {} msat", min, max)
+            }
+            BadRequest::InvalidPayerData(reason) => write!(f, "invalid payerData: {}", reason),
+            BadRequest::UnsupportedCurrency(reason) => write!(f, "invalid currency: {}", reason),
+            BadRequest::InvalidZapRequest(reason) => write!(f, "invalid zap request: {}", reason),
+        }
+    }
+}
+
+impl BadRequest {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            BadRequest::CommentTooLong { .. } => ErrorCode::CommentTooLong,
+            BadRequest::AmountOutOfRange { .. } => ErrorCode::AmountOutOfRange,
+            BadRequest::InvalidPayerData(_) => ErrorCode::InvalidPayerData,
+            BadRequest::UnsupportedCurrency(_) => ErrorCode::UnsupportedCurrency,
+            BadRequest::InvalidZapRequest(_) => ErrorCode::InvalidZapRequest,
+        }
+    }
+}
+
+impl From<BadRequest> for HttpError {
+    fn from(e: BadRequest) -> Self {
+        let body = Lud06Error::new(e.to_string()).with_code(e.code());
+        HttpError::new(StatusCode::BAD_REQUEST, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_error_serializes_as_response_body() {
+        let e = HttpError::new(
+            StatusCode::NOT_FOUND,
+            Lud06Error::new("user bob not found".to_string()),
+        )
+        .with_request_id("abc-123".to_string());
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"status": "ERROR", "reason": "user bob not found", "requestId": "abc-123"})
+        );
+
+        let body: Lud06Error = serde_json::from_value(json).unwrap();
+        assert_eq!(body.status(), "ERROR");
+        assert_eq!(body.reason(), e.body().reason());
+        assert_eq!(body.request_id(), Some("abc-123"));
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn nwc_errors_are_mapped() {
+        let e = HttpError::from(nwc::Error::Timeout);
+        assert_eq!(e.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(e.body().code(), Some(1012));
+
+        let wallet_error = |code| {
+            nwc::Error::NIP47(nip47::Error::ErrorCode(nip47::NIP47Error {
+                code,
+                message: "nope".to_string(),
+            }))
+        };
+        let e = HttpError::from(wallet_error(nip47::ErrorCode::Restricted));
+        assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(e.body().code(), Some(1013));
+        let e = HttpError::from(wallet_error(nip47::ErrorCode::RateLimited));
+        assert_eq!(e.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(e.body().code(), Some(1004));
+        let e = HttpError::from(wallet_error(nip47::ErrorCode::Internal));
+        assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(e.body().code(), Some(1003));
+    }
+
+    #[test]
+    fn codes_are_serialized() {
+        let e = HttpError::new(StatusCode::NOT_FOUND, Lud06Error::user_not_found("bob"));
+        let json = serde_json::to_value(&e).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"status": "ERROR", "reason": "user bob not found", "code": 1001})
+        );
+
+        let e = HttpError::from(BadRequest::AmountOutOfRange { min: 1, max: 2 });
+        assert_eq!(e.body().code(), Some(1002));
+        let e = HttpError::too_many_requests(Duration::from_secs(1));
+        assert_eq!(e.body().code(), Some(1004));
+    }
+}
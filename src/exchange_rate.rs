@@ -0,0 +1,270 @@
+use crate::config::{CurrencyConfig, ExchangeRateConfig};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// - [LUD-21: Currencies in payRequest](https://github.com/lnurl/luds/pull/251)
+
+const MSAT_PER_BTC: f64 = 100_000_000_000.0;
+
+// price of 1 BTC in each currency, keyed by lowercase currency code.
+type Prices = Arc<HashMap<String, f64>>;
+
+/// A currency as advertised in the `currencies` field of the lnurlp info.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Currency {
+    pub code: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u32,
+    /// msat per smallest unit of the currency, e.g. per cent for USD.
+    pub multiplier: f64,
+    pub convertible: bool,
+}
+
+/// Fetches BTC prices from the configured provider, and caches them for `ttl`. Once they're stale,
+/// one request fetches them again while the others keep getting the stale ones, as does that
+/// request if the provider fails.
+pub struct ExchangeRates {
+    client: reqwest::Client,
+    provider_url: String,
+    currencies: Vec<CurrencyConfig>,
+    ttl: Duration,
+    // never locked across awaits.
+    cache: RwLock<Option<(Instant, Prices)>>,
+    // held by the request fetching the prices.
+    fetching: Mutex<()>,
+    // code and decimals of the currency amounts are logged in, fetched along the others.
+    display_currency: Option<(String, u8)>,
+}
+
+impl ExchangeRates {
+    pub fn new(config: &ExchangeRateConfig) -> Result<ExchangeRates> {
+        Ok(ExchangeRates {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            provider_url: config.provider_url.clone(),
+            currencies: config.currencies.clone(),
+            ttl: Duration::from_secs(config.ttl_seconds),
+            cache: RwLock::new(None),
+            fetching: Mutex::new(()),
+            display_currency: None,
+        })
+    }
+
+    /// Also fetches the price of `code`, for `display_amount`.
+    pub fn with_display_currency(mut self, code: &str, decimals: u8) -> Self {
+        self.display_currency = Some((code.to_string(), decimals));
+        self
+    }
+
+    /// `amount_msat` in the display currency, e.g. `12.34 USD`, for logs. Only the cached price is
+    /// used, however old, so that logging never waits for the provider. It's fetched whenever
+    /// wallets ask for the pay info, which they do before each payment.
+    pub fn display_amount(&self, amount_msat: u64) -> Option<String> {
+        let (code, decimals) = self.display_currency.as_ref()?;
+        let cache = self.cache.read().unwrap();
+        let (_, prices) = cache.as_ref()?;
+        let price = prices.get(&code.to_ascii_lowercase())?;
+        let amount = amount_msat as f64 / MSAT_PER_BTC * price;
+        Some(format!("{:.*} {}", *decimals as usize, amount, code))
+    }
+
+    /// All the configured currencies, with their current multiplier.
+    pub async fn currencies(&self) -> Result<Vec<Currency>> {
+        let prices = self.prices().await?;
+        self.currencies
+            .iter()
+            .map(|currency| to_currency(currency, &prices))
+            .collect()
+    }
+
+    /// The configured currency of `code`, in any case.
+    pub fn currency(&self, code: &str) -> Option<&CurrencyConfig> {
+        self.currencies
+            .iter()
+            .find(|currency| currency.code.eq_ignore_ascii_case(code))
+    }
+
+    /// Converts `amount`, in the smallest unit of `currency`, to msat. Fails only if the price of
+    /// the currency can't be had.
+    pub async fn to_msat(&self, amount: u64, currency: &CurrencyConfig) -> Result<u64> {
+        let prices = self.prices().await?;
+        let multiplier = to_currency(currency, &prices)?.multiplier;
+        Ok((amount as f64 * multiplier).round() as u64)
+    }
+
+    async fn prices(&self) -> Result<Prices> {
+        let cached = self.cache.read().unwrap().clone();
+        let (fetching, stale) = match cached {
+            Some((fetched_at, prices)) if fetched_at.elapsed() < self.ttl => return Ok(prices),
+            Some((_, stale)) => match self.fetching.try_lock() {
+                Ok(fetching) => (fetching, Some(stale)),
+                Err(_) => return Ok(stale),
+            },
+            // nothing to answer with until the first fetch is done.
+            None => {
+                let fetching = self.fetching.lock().await;
+                if let Some((_, prices)) = self.cache.read().unwrap().as_ref() {
+                    return Ok(prices.clone());
+                }
+                (fetching, None)
+            }
+        };
+        let prices = match (self.fetch_prices().await, stale) {
+            (Ok(prices), _) => Arc::new(prices),
+            // stale prices are better than no invoice at all, the next request tries again.
+            (Err(e), Some(stale)) => {
+                tracing::warn!("failed to fetch exchange rates, using stale ones: {}", e);
+                return Ok(stale);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        *self.cache.write().unwrap() = Some((Instant::now(), prices.clone()));
+        drop(fetching);
+        Ok(prices)
+    }
+
+    // The provider must speak the coingecko `simple/price` API, e.g.
+    // `{"bitcoin": {"usd": 65000.0, "eur": 60000.0}}`
+    async fn fetch_prices(&self) -> Result<HashMap<String, f64>> {
+        let vs_currencies = self
+            .currencies
+            .iter()
+            .map(|currency| currency.code.as_str())
+            .chain(
+                self.display_currency
+                    .as_ref()
+                    .map(|(code, _)| code.as_str()),
+            )
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>()
+            .join(",");
+        let res: SimplePriceResponse = self
+            .client
+            .get(&self.provider_url)
+            .query(&[("ids", "bitcoin"), ("vs_currencies", &vs_currencies)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(res.bitcoin)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_prices(
+        currencies: Vec<CurrencyConfig>,
+        prices: HashMap<String, f64>,
+    ) -> Self {
+        ExchangeRates {
+            client: reqwest::Client::new(),
+            provider_url: String::new(),
+            currencies,
+            ttl: Duration::MAX,
+            cache: RwLock::new(Some((Instant::now(), Arc::new(prices)))),
+            fetching: Mutex::new(()),
+            display_currency: None,
+        }
+    }
+}
+
+fn to_currency(currency: &CurrencyConfig, prices: &HashMap<String, f64>) -> Result<Currency> {
+    let price = match prices.get(&currency.code.to_ascii_lowercase()) {
+        Some(price) if *price > 0.0 => *price,
+        _ => anyhow::bail!("no exchange rate for {}", currency.code),
+    };
+    let multiplier = MSAT_PER_BTC / (price * 10f64.powi(currency.decimals as i32));
+    Ok(Currency {
+        code: currency.code.clone(),
+        name: currency.name.clone(),
+        symbol: currency.symbol.clone(),
+        decimals: currency.decimals,
+        multiplier,
+        convertible: true,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct SimplePriceResponse {
+    bitcoin: HashMap<String, f64>,
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn usd() -> CurrencyConfig {
+        CurrencyConfig {
+            code: "USD".to_string(),
+            name: "US Dollar".to_string(),
+            symbol: "$".to_string(),
+            decimals: 2,
+        }
+    }
+
+    pub(crate) fn usd_rates() -> ExchangeRates {
+        // 1 BTC = 100,000 USD, so 1 cent = 10 sat = 10,000 msat.
+        ExchangeRates::with_prices(vec![usd()], HashMap::from([("usd".to_string(), 100_000.0)]))
+    }
+
+    #[tokio::test]
+    async fn currencies_include_multiplier() -> Result<()> {
+        let currencies = usd_rates().currencies().await?;
+        assert_eq!(currencies.len(), 1);
+        assert_eq!(currencies[0].code, "USD");
+        assert_eq!(currencies[0].multiplier, 10_000.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn to_msat_converts_smallest_unit() -> Result<()> {
+        let rates = usd_rates();
+        assert_eq!(
+            rates.to_msat(150, rates.currency("usd").unwrap()).await?,
+            1_500_000
+        );
+        assert!(rates.currency("EUR").is_none());
+
+        let rates = ExchangeRates::with_prices(vec![usd()], HashMap::new());
+        assert!(rates.to_msat(150, &usd()).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn display_amount_uses_cached_price() {
+        assert_eq!(usd_rates().display_amount(1_500_000), None);
+        let rates = usd_rates().with_display_currency("USD", 2);
+        assert_eq!(rates.display_amount(1_500_000).as_deref(), Some("1.50 USD"));
+        let rates = usd_rates().with_display_currency("EUR", 2);
+        assert_eq!(rates.display_amount(1_500_000), None);
+    }
+
+    #[tokio::test]
+    async fn stale_prices_are_used_while_fetching() -> Result<()> {
+        let mut rates = usd_rates();
+        rates.ttl = Duration::ZERO;
+        let fetching = rates.fetching.lock().await;
+        assert_eq!(rates.to_msat(150, &usd()).await?, 1_500_000);
+        drop(fetching);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale_prices_are_used_when_provider_fails() -> Result<()> {
+        let mut rates = usd_rates();
+        rates.provider_url = "http://127.0.0.1:1/simple/price".to_string();
+        rates.ttl = Duration::ZERO;
+        assert_eq!(rates.to_msat(150, &usd()).await?, 1_500_000);
+        assert_eq!(rates.to_msat(150, &usd()).await?, 1_500_000);
+
+        // without stale prices, there's nothing to fall back to.
+        *rates.cache.write().unwrap() = None;
+        assert!(rates.to_msat(150, &usd()).await.is_err());
+        Ok(())
+    }
+}
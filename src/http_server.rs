@@ -1,100 +1,872 @@
-use crate::config::Config;
-use crate::error::{HttpError, Lud06Error};
-use crate::invoice_creator::{InvoiceCreator, NwcInvoiceCreator};
-use anyhow::Result;
-use axum::Router;
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
-use axum::response::Json;
+mod access_log;
+mod admin;
+mod balance;
+mod dedup;
+mod disposable;
+mod health;
+mod info;
+mod invoice_page;
+mod last_invoice;
+mod latency;
+mod lnurl;
+mod quota;
+mod request_id;
+mod status;
+mod vhost;
+mod withdraw;
+
+use crate::avatar::Avatar;
+use crate::config::{
+    Config, DEFAULT_INVOICE_MAX_ATTEMPTS, RetryPolicy, ServerConfig, UserConfig, WithdrawConfig,
+};
+use crate::config_watcher::{reload_on_sighup, watch_config};
+use crate::error::{BadRequest, ErrorCode, HttpError, Lud06Error};
+use crate::exchange_rate::{Currency, ExchangeRates};
+use crate::invoice_creator::{
+    InvoiceCreator, InvoiceCreatorFactory, InvoiceStatus, RetryableInvoiceCreator,
+};
+use crate::invoice_log::{InvoiceAttempt, InvoiceLog};
+use crate::metrics::{
+    INVOICE_ERRORS, INVOICES_CREATED, LNURLP_INFO_REQUESTS, NWC_ATTEMPT_DURATION,
+    run_metrics_server,
+};
+use crate::payer_data::{PayerData, PayerDataConfig};
+use crate::rate_limit::{
+    ClientIp, IpRateLimiter, UserRateLimiters, add_client_ip, ip_rate_limit, quota,
+};
+use crate::shutdown::{ShutdownListener, shutdown_signal};
+use crate::success_action::SuccessAction;
+use crate::webhook::{InvoiceCreated, Webhook};
+use crate::zap::{MAX_PENDING_ZAPS, Zapper, parse_zap_request};
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
 use axum::routing::get;
-use bitcoin_hashes::Sha256;
+use axum::{Router, middleware};
+use axum_server::tls_rustls::RustlsConfig;
+use futures::future::{BoxFuture, try_join_all};
+use governor::Quota;
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::set_header::SetResponseHeaderLayer;
+use tower_http::timeout::TimeoutLayer;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use access_log::add_access_log;
+use admin::{admin_router, run_admin_server};
+use dedup::RecentInvoices;
+use disposable::{DisposableLinks, create_disposable_invoice, get_disposable_lnurlp_info};
+use health::{HealthCache, get_health, warm_up};
+use info::get_info;
+use invoice_page::get_invoice_page;
+use last_invoice::LastInvoices;
+use latency::BackendLatencies;
+use lnurl::{get_address_qr, get_lnurl, get_lnurl_qr, get_usernames};
+use quota::{DailyTotals, reset_daily_totals};
+use request_id::{RequestId, request_id};
+use status::BackendStatsMap;
+use vhost::virtual_hosts;
+use withdraw::{WithdrawSessions, get_lnurlw_info, withdraw};
+
+/// The state shared by all handlers. It can be swapped atomically when the config is reloaded.
+pub type SharedState = Arc<ArcSwap<AppState>>;
 
 pub struct AppState {
-    domain: String,
-    users: HashMap<String, Vec<Box<dyn InvoiceCreator>>>,
+    pub(crate) domain: String,
+    pub(crate) use_forwarded_host: bool,
+    // users can be added or removed at runtime through the admin API.
+    // aliases are keys of the same user as its name. Keys are lowercase, see `user`.
+    pub(crate) users: RwLock<HashMap<String, Arc<User>>>,
+    // LUD-19: users by their nostr public key, in lowercase hex.
+    pub(crate) nostr_pubkeys: RwLock<HashMap<String, Arc<User>>>,
+    pub(crate) health_check_timeout: Duration,
+    // users can change with a reload, so it's not carried over.
+    pub(crate) health_cache: HealthCache,
+    pub(crate) max_avatar_bytes: u64,
+    pub(crate) backend_stats: BackendStatsMap,
+    pub(crate) backend_latencies: Arc<BackendLatencies>,
+    pub(crate) daily_totals: Arc<DailyTotals>,
+    pub(crate) recent_invoices: Arc<RecentInvoices>,
+    pub(crate) last_invoices: Arc<LastInvoices>,
+    pub(crate) disposable_links: Arc<DisposableLinks>, // LUD-11
+    pub(crate) withdraw_sessions: Arc<WithdrawSessions>, // LUD-03
+    pub(crate) withdrawn_totals: Arc<DailyTotals>,
+    pub(crate) user_rate_limiters: Arc<UserRateLimiters>,
+    // NIP-57: bounds the invoices polled for their zap receipt, across all users.
+    pub(crate) zap_pollers: Arc<Semaphore>,
+    pub(crate) disposable_link_ttl: Duration,
+    pub(crate) catch_all_user: Option<String>,
+    pub(crate) list_users: bool,
+    pub(crate) exchange_rates: Option<ExchangeRates>, // LUD-21
+    pub(crate) invoice_log: Option<InvoiceLog>,
+    pub(crate) invoice_creator_factory: Arc<dyn InvoiceCreatorFactory>,
+    // when the server started, carried over reloads.
+    pub(crate) started_at: Instant,
+}
+
+/// A change of the users between two states, see `AppState::user_changes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserChange {
+    Added(String),
+    Removed(String),
+    BackendsChanged(String),
+}
+
+pub struct User {
+    name: String, // the canonical name in lowercase, used rather than the alias a request is made to.
+    enabled: bool,
+    // the primary creators come first, followed by the fallback ones.
+    invoice_creators: Vec<Arc<dyn InvoiceCreator>>,
+    primary_creators: usize,
+    comment_allowed: u64, // LUD-12, max length of comment. 0 means comments are not allowed.
+    success_action: Option<SuccessAction>, // LUD-09
+    min_sendable: u64,    // msat
+    max_sendable: u64,    // msat
+    rate_limit: Option<Quota>, // see `AppState::user_rate_limiters`
+    concurrency_limit: Option<Semaphore>,
+    invoice_expiry: u64, // seconds
+    retry_policy: RetryPolicy,
+    invoice_max_attempts: Option<usize>, // backends tried, DEFAULT_INVOICE_MAX_ATTEMPTS if absent
+    payer_data: Option<PayerDataConfig>, // LUD-18
+    withdraw: Option<WithdrawConfig>,    // LUD-03
+    zapper: Option<Arc<Zapper>>,         // NIP-57
+    display_name: Option<String>,
+    invoice_description_prefix: Option<String>,
+    description: Option<String>,
+    avatar: Option<Avatar>,
+    webhook: Option<Arc<Webhook>>,
+    daily_invoice_cap: Option<u64>, // msat
+    invoice_dedup_ttl: Option<Duration>,
 }
 
 impl AppState {
-    pub fn new(config: &Config) -> Result<AppState> {
-        let mut state = AppState {
-            domain: config.server.domain.clone(),
-            users: HashMap::new(),
-        };
+    /// The state of the primary domain, `server.domain`.
+    /// `invoice_creator_factory` is kept to build the users added later, by reloads or the admin API.
+    pub fn new(
+        config: &Config,
+        invoice_creator_factory: Arc<dyn InvoiceCreatorFactory>,
+    ) -> Result<AppState> {
+        AppState::build(
+            config,
+            &config.server.domain,
+            &config.users,
+            invoice_creator_factory,
+        )
+    }
+
+    /// The state of one of the other `domains`. Server wide settings are shared with the primary domain.
+    pub fn for_domain(
+        config: &Config,
+        domain: &str,
+        invoice_creator_factory: Arc<dyn InvoiceCreatorFactory>,
+    ) -> Result<AppState> {
+        let domain_config = config
+            .domains
+            .iter()
+            .find(|domain_config| domain_config.domain == domain)
+            .ok_or_else(|| anyhow::anyhow!("domain {} is not configured", domain))?;
+        AppState::build(
+            config,
+            &domain_config.domain,
+            &domain_config.users,
+            invoice_creator_factory,
+        )
+    }
 
-        for user_config in &config.users {
-            let mut invoice_creators: Vec<Box<dyn InvoiceCreator>> = vec![];
-            for nwc_str in &user_config.nwcs {
-                let nwc_invoice_creator = NwcInvoiceCreator::new(nwc_str)?;
-                invoice_creators.push(Box::new(nwc_invoice_creator));
+    fn build(
+        config: &Config,
+        domain: &str,
+        user_configs: &[UserConfig],
+        invoice_creator_factory: Arc<dyn InvoiceCreatorFactory>,
+    ) -> Result<AppState> {
+        let mut users = HashMap::new();
+        let mut nostr_pubkeys = HashMap::new();
+        for user_config in user_configs {
+            let user = User::new(
+                user_config,
+                config.server.max_avatar_bytes,
+                invoice_creator_factory.as_ref(),
+            )?;
+            let user = Arc::new(user);
+            for alias in &user_config.aliases {
+                users.insert(alias.to_lowercase(), user.clone());
             }
-            state
-                .users
-                .insert(user_config.name.clone(), invoice_creators);
+            if let Some(pubkey) = user_config.nostr_pubkey_hex()? {
+                nostr_pubkeys.insert(pubkey, user.clone());
+            }
+            users.insert(user.name.clone(), user);
         }
+        Ok(AppState {
+            domain: domain.to_string(),
+            use_forwarded_host: config.server.use_forwarded_host,
+            users: RwLock::new(users),
+            nostr_pubkeys: RwLock::new(nostr_pubkeys),
+            health_check_timeout: Duration::from_millis(config.server.health_check_timeout_ms),
+            health_cache: HealthCache::default(),
+            max_avatar_bytes: config.server.max_avatar_bytes,
+            backend_stats: BackendStatsMap::default(),
+            backend_latencies: Arc::default(),
+            daily_totals: Arc::default(),
+            recent_invoices: Arc::default(),
+            last_invoices: Arc::default(),
+            disposable_links: Arc::default(),
+            withdraw_sessions: Arc::default(),
+            withdrawn_totals: Arc::default(),
+            user_rate_limiters: Arc::default(),
+            zap_pollers: Arc::new(Semaphore::new(MAX_PENDING_ZAPS)),
+            disposable_link_ttl: Duration::from_secs(config.server.disposable_link_ttl_seconds),
+            catch_all_user: config.server.catch_all_user.clone(),
+            list_users: config.server.list_users,
+            exchange_rates: config
+                .server
+                .exchange_rate
+                .as_ref()
+                .map(|exchange_rate| {
+                    let rates = ExchangeRates::new(exchange_rate)?;
+                    Ok::<_, anyhow::Error>(match &config.server.display_currency {
+                        Some(code) => rates
+                            .with_display_currency(code, config.server.display_currency_decimals),
+                        None => rates,
+                    })
+                })
+                .transpose()?,
+            invoice_log: config.server.invoice_log_db.as_deref().map(InvoiceLog::new),
+            invoice_creator_factory,
+            started_at: Instant::now(),
+        })
+    }
 
+    /// Builds the state from a reloaded config, see `carry_over`.
+    pub fn reload(&self, config: &Config) -> Result<AppState> {
+        let mut state = AppState::new(config, self.invoice_creator_factory.clone())?;
+        self.carry_over(&mut state);
         Ok(state)
     }
+
+    /// Like `reload`, for the state built by `for_domain`. Fails if the domain has been removed,
+    /// since its listener can't be closed without a restart.
+    pub fn reload_domain(&self, config: &Config) -> Result<AppState> {
+        let mut state =
+            AppState::for_domain(config, &self.domain, self.invoice_creator_factory.clone())?;
+        self.carry_over(&mut state);
+        Ok(state)
+    }
+
+    /// Keeps in the state built from a reloaded config what must survive reloads: the daily
+    /// totals, the recent invoices, the last invoices, the disposable links, the withdraw sessions
+    /// and totals, the rate limiters of the users, the zap pollers, and the start time. Both
+    /// `reload` and `reload_domain` go through here, so that none is forgotten by either.
+    fn carry_over(&self, state: &mut AppState) {
+        state.daily_totals = self.daily_totals.clone();
+        state.recent_invoices = self.recent_invoices.clone();
+        state.last_invoices = self.last_invoices.clone();
+        state.disposable_links = self.disposable_links.clone();
+        state.withdraw_sessions = self.withdraw_sessions.clone();
+        state.withdrawn_totals = self.withdrawn_totals.clone();
+        state.user_rate_limiters = self.user_rate_limiters.clone();
+        state.zap_pollers = self.zap_pollers.clone();
+        state.started_at = self.started_at;
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// What changed for users from this state to `new`, by their canonical name. Backends are
+    /// compared by their endpoints, which don't include secrets.
+    pub fn user_changes(&self, new: &AppState) -> Vec<UserChange> {
+        let backends = |state: &AppState| {
+            let users = state.users.read().unwrap_or_else(|e| e.into_inner());
+            users
+                .values()
+                .map(|user| {
+                    let endpoints: Vec<String> = user
+                        .invoice_creators
+                        .iter()
+                        .map(|creator| creator.endpoint())
+                        .collect();
+                    (user.name.clone(), endpoints)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let (old, new) = (backends(self), backends(new));
+        let mut changes = vec![];
+        for (name, endpoints) in &new {
+            match old.get(name) {
+                None => changes.push(UserChange::Added(name.clone())),
+                Some(old_endpoints) if old_endpoints != endpoints => {
+                    changes.push(UserChange::BackendsChanged(name.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for name in old.keys().filter(|name| !new.contains_key(*name)) {
+            changes.push(UserChange::Removed(name.clone()));
+        }
+        changes
+    }
+
+    /// Usernames are case-insensitive, like email addresses. Disabled users are not found.
+    fn user(&self, username: &str) -> Option<Arc<User>> {
+        self.any_user(username).filter(|user| user.enabled)
+    }
+
+    /// Like `user`, disabled users included.
+    fn any_user(&self, username: &str) -> Option<Arc<User>> {
+        self.users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&username.to_lowercase())
+            .cloned()
+    }
+
+    /// The user paid at `username`, with the name its metadata is made for. That's the user's own
+    /// name, or `username` itself when it's only matched by `catch_all_user`.
+    /// LUD-19: a username which is a hex nostr public key is paid to the user with that `nostr_pubkey`.
+    fn payee(&self, username: &str) -> Result<(String, Arc<User>), HttpError> {
+        let not_found = |e: Lud06Error| HttpError::new(StatusCode::NOT_FOUND, e);
+        // a disabled user isn't paid to the catch-all user either, the payments are meant for it.
+        match self.any_user(username) {
+            Some(user) if user.enabled => return Ok((user.name.clone(), user)),
+            Some(_) => return Err(not_found(Lud06Error::user_not_found(username))),
+            None => {}
+        }
+        if is_hex_pubkey(username) {
+            let user = self
+                .nostr_pubkeys
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&username.to_lowercase())
+                .filter(|user| user.enabled)
+                .cloned()
+                .ok_or_else(|| {
+                    let e = Lud06Error::new(format!("no user has nostr pubkey {}", username));
+                    not_found(e.with_code(ErrorCode::UserNotFound))
+                })?;
+            return Ok((user.name.clone(), user));
+        }
+        // the name ends up in the callback url and the metadata, only LUD-16 usernames are taken.
+        let catch_all_user = self
+            .catch_all_user
+            .as_deref()
+            .filter(|_| is_lud16_username(username))
+            .and_then(|catch_all_user| self.user(catch_all_user));
+        match catch_all_user {
+            Some(user) => Ok((username.to_lowercase(), user)),
+            None => Err(not_found(Lud06Error::user_not_found(username))),
+        }
+    }
+
+    /// A snapshot of all users, so that the lock is not held across awaits. Aliases are left out.
+    fn all_users(&self) -> Vec<(String, Arc<User>)> {
+        self.users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(username, user)| **username == user.name)
+            .map(|(username, user)| (username.clone(), user.clone()))
+            .collect()
+    }
+
+    /// Like `all_users`, without the disabled users, which must look like they don't exist.
+    fn enabled_users(&self) -> Vec<(String, Arc<User>)> {
+        let mut users = self.all_users();
+        users.retain(|(_, user)| user.enabled);
+        users
+    }
+}
+
+impl User {
+    /// The avatar is read here, so that it's also picked up again when the config is reloaded.
+    pub fn new(
+        user_config: &UserConfig,
+        max_avatar_bytes: u64,
+        invoice_creator_factory: &dyn InvoiceCreatorFactory,
+    ) -> Result<User> {
+        let creators = invoice_creator_factory.create_from_config(user_config)?;
+        User::with_invoice_creators(
+            user_config,
+            max_avatar_bytes,
+            creators.primary,
+            creators.fallback,
+        )
+    }
+
+    pub(crate) fn with_invoice_creators(
+        user_config: &UserConfig,
+        max_avatar_bytes: u64,
+        mut invoice_creators: Vec<Arc<dyn InvoiceCreator>>,
+        fallback_creators: Vec<Arc<dyn InvoiceCreator>>,
+    ) -> Result<User> {
+        let primary_creators = invoice_creators.len();
+        invoice_creators.extend(fallback_creators);
+        Ok(User {
+            name: user_config.name.to_lowercase(),
+            enabled: user_config.enabled,
+            invoice_creators,
+            primary_creators,
+            comment_allowed: user_config.comment_allowed,
+            success_action: user_config.success_action.clone(),
+            min_sendable: user_config.min_sendable_msat,
+            max_sendable: user_config.max_sendable_msat,
+            rate_limit: user_config.rate_limit.as_ref().map(quota),
+            concurrency_limit: user_config
+                .max_concurrent_requests
+                .map(|permits| Semaphore::new(permits as usize)),
+            invoice_expiry: user_config.invoice_expiry_seconds,
+            retry_policy: user_config.retry_policy.clone(),
+            invoice_max_attempts: user_config.invoice_max_attempts,
+            payer_data: user_config.payer_data.clone(),
+            withdraw: user_config.withdraw.clone(),
+            zapper: user_config
+                .zap
+                .as_ref()
+                .map(Zapper::new)
+                .transpose()?
+                .map(Arc::new),
+            display_name: user_config.display_name.clone(),
+            invoice_description_prefix: user_config.invoice_description_prefix.clone(),
+            description: user_config.description.clone(),
+            avatar: user_config
+                .avatar_path
+                .as_deref()
+                .map(|path| Avatar::load(path, max_avatar_bytes))
+                .transpose()?,
+            webhook: user_config
+                .webhook_url
+                .as_deref()
+                .map(|url| Webhook::new(url, Duration::from_millis(user_config.webhook_timeout_ms)))
+                .transpose()?
+                .map(Arc::new),
+            daily_invoice_cap: user_config.daily_invoice_cap_msat,
+            invoice_dedup_ttl: user_config
+                .invoice_dedup_ttl_seconds
+                .map(Duration::from_secs),
+        })
+    }
 }
 
 // lightning address specs:
 // - [LUD-16: Paying to static internet identifiers](https://github.com/lnurl/luds/blob/luds/16.md)
 // - [LUD-06: payRequest base spec](https://github.com/lnurl/luds/blob/luds/06.md)
-pub async fn run_http_server(config: &Config) -> Result<()> {
-    let state = Arc::new(AppState::new(config)?);
+pub async fn run_http_server(
+    config: &Config,
+    config_paths: &[std::path::PathBuf],
+    invoice_creator_factory: Arc<dyn InvoiceCreatorFactory>,
+) -> Result<()> {
+    let state: SharedState = Arc::new(ArcSwap::from_pointee(AppState::new(
+        config,
+        invoice_creator_factory.clone(),
+    )?));
+    if let Some(invoice_log) = &state.load().invoice_log {
+        invoice_log.migrate().await?;
+    }
+    let mut domain_states = vec![];
+    for domain_config in &config.domains {
+        let domain_state: SharedState = Arc::new(ArcSwap::from_pointee(AppState::for_domain(
+            config,
+            &domain_config.domain,
+            invoice_creator_factory.clone(),
+        )?));
+        domain_states.push(domain_state);
+    }
+    let states: Vec<_> = std::iter::once(&state)
+        .chain(&domain_states)
+        .map(|state| state.load_full())
+        .collect();
+    let failures = warm_up(&states).await;
+    if !failures.is_empty() && config.server.fail_on_warmup_error {
+        anyhow::bail!("backends unreachable on startup:\n{}", failures.join("\n"));
+    }
+    let _watcher = watch_config(config_paths, state.clone(), domain_states.clone())?;
+    reload_on_sighup(config_paths, state.clone(), domain_states.clone())?;
+    for state in std::iter::once(&state).chain(&domain_states) {
+        tokio::spawn(reset_daily_totals(state.clone()));
+    }
+
+    // the rate limit is per IP across all domains.
+    let ip_rate_limiter = config
+        .server
+        .ip_rate_limit
+        .as_ref()
+        .map(|ip_rate_limit_config| {
+            IpRateLimiter::new(ip_rate_limit_config, config.server.trust_proxy)
+        });
+    let ip_limited = |app: Router| match &ip_rate_limiter {
+        Some(ip_rate_limiter) => app.layer(middleware::from_fn_with_state(
+            ip_rate_limiter.clone(),
+            ip_rate_limit,
+        )),
+        None => app,
+    };
+    let public_app = |app: Router| ip_limited(app).layer(cors_layer(&config.server));
+    let (shutdown_tx, shutdown) = ShutdownListener::new();
+    let mut servers: Vec<BoxFuture<Result<()>>> = vec![];
+
+    // domains with their own listen_addr are served alone, the others are told apart by host.
+    let mut hosts = HashMap::new();
+    for (domain_config, domain_state) in config.domains.iter().zip(&domain_states) {
+        let domain_app = router(domain_state.clone());
+        match &domain_config.listen_addr {
+            Some(listen_addr) => {
+                let domain_app = public_app(domain_app);
+                let domain_app = add_request_limits(domain_app, &config.server);
+                let domain_app = add_security_headers(domain_app, &config.server);
+                let domain_app = add_access_log(domain_app, config.server.trust_proxy)
+                    .layer(middleware::from_fn_with_state(
+                        config.server.trust_proxy,
+                        add_client_ip,
+                    ))
+                    .layer(middleware::from_fn(request_id));
+                servers.push(Box::pin(serve(
+                    domain_app,
+                    listen_addr,
+                    &config.server,
+                    shutdown.clone(),
+                )));
+            }
+            None => {
+                hosts.insert(domain_config.domain.clone(), domain_app);
+            }
+        }
+    }
+    let mut app = router(state.clone());
+    if !hosts.is_empty() {
+        app = virtual_hosts(app, hosts);
+    }
+    let mut app = public_app(app);
 
-    let app = Router::new()
-        .route("/.well-known/lnurlp/{username}", get(get_lnurlp_info))
+    // the admin API is only mounted when a token is configured, and never goes through CORS.
+    let admin_app = config.server.admin_token.as_deref().map(|admin_token| {
+        add_request_limits(admin_router(state.clone(), admin_token), &config.server)
+    });
+    match (admin_app, &config.server.admin_listen_addr) {
+        (Some(admin_app), Some(admin_listen_addr)) => servers.push(Box::pin(run_admin_server(
+            admin_listen_addr,
+            add_access_log(admin_app, config.server.trust_proxy)
+                .layer(middleware::from_fn(request_id)),
+            shutdown.clone(),
+        ))),
+        // on the public address, it's as exposed to token guessing as the rest.
+        (Some(admin_app), None) => app = app.merge(ip_limited(admin_app)),
+        (None, _) => {}
+    }
+    if let Some(metrics_listen_addr) = &config.server.metrics_listen_addr {
+        servers.push(Box::pin(run_metrics_server(
+            metrics_listen_addr,
+            shutdown.clone(),
+        )));
+    }
+    let app = add_request_limits(app, &config.server);
+    // outermost, so that even the responses of the other middlewares carry the request id.
+    let app = add_security_headers(app, &config.server);
+    let app = add_access_log(app, config.server.trust_proxy)
+        .layer(middleware::from_fn_with_state(
+            config.server.trust_proxy,
+            add_client_ip,
+        ))
+        .layer(middleware::from_fn(request_id));
+    // every address serves the same app, over the same state.
+    for listen_addr in &config.server.listen_addrs {
+        servers.push(Box::pin(serve(
+            app.clone(),
+            listen_addr,
+            &config.server,
+            shutdown.clone(),
+        )));
+    }
+    let servers = try_join_all(servers);
+
+    // stop accepting new connections on shutdown, and give in-flight requests some time to finish.
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_seconds);
+    tokio::select! {
+        res = servers => { res?; }
+        _ = async {
+            shutdown_signal().await;
+            let _ = shutdown_tx.send(());
+            tokio::time::sleep(shutdown_timeout).await;
+        } => {
+            tracing::warn!("in-flight requests not finished in {:?}, closing them forcibly.", shutdown_timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Values are checked by `Config::validate`, invalid ones are skipped.
+fn cors_layer(server_config: &ServerConfig) -> CorsLayer {
+    let is_any = |values: &[String]| values.iter().any(|value| value == "*");
+    let origins = &server_config.cors_allowed_origins;
+    let allow_origin = if is_any(origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|origin| origin.parse().ok()))
+    };
+    let methods = &server_config.cors_allowed_methods;
+    let allow_methods = if is_any(methods) {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(methods.iter().filter_map(|method| method.parse().ok()))
+    };
+    let headers = &server_config.cors_allowed_headers;
+    let allow_headers = if is_any(headers) {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(headers.iter().filter_map(|header| header.parse().ok()))
+    };
+    let layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers);
+    match server_config.cors_max_age_seconds {
+        Some(seconds) => layer.max_age(Duration::from_secs(seconds.into())),
+        None => layer,
+    }
+}
+
+/// Bounds the size of request bodies, and how long a request can take.
+fn add_request_limits(app: Router, server_config: &ServerConfig) -> Router {
+    app.layer(RequestBodyLimitLayer::new(
+        server_config.max_request_body_bytes,
+    ))
+    .layer(TimeoutLayer::with_status_code(
+        StatusCode::GATEWAY_TIMEOUT,
+        Duration::from_millis(server_config.request_timeout_ms),
+    ))
+}
+
+fn add_security_headers(mut app: Router, server_config: &ServerConfig) -> Router {
+    let headers = server_config
+        .security_headers
+        .headers(server_config.tls.is_some());
+    for (name, value) in headers {
+        // values are checked by `Config::validate`.
+        if let Ok(value) = HeaderValue::from_str(value) {
+            app = app.layer(SetResponseHeaderLayer::if_not_present(
+                HeaderName::from_static(name),
+                value,
+            ));
+        }
+    }
+    app
+}
+
+/// The public routes, without the middlewares and the admin API added by `run_http_server`.
+pub fn router(state: SharedState) -> Router {
+    Router::new()
+        .route(
+            "/.well-known/lnurlp/{username}",
+            get(get_lnurlp_info_or_address_qr),
+        )
+        .route("/.well-known/lnurlp/{username}/qr", get(get_lnurl_qr))
+        .route(
+            "/.well-known/lnurlp/{username}/once",
+            get(get_disposable_lnurlp_info),
+        )
+        .route("/lnurlp", get(get_usernames))
         .route("/lnurlp/{username}", get(create_invoice))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+        .route("/lnurlp/{username}/invoice", get(get_invoice_page))
+        .route("/lnurlp/once/{token}", get(create_disposable_invoice))
+        .route("/lnurl/{username}", get(get_lnurl))
+        .route("/.well-known/lnurlw/{username}", get(get_lnurlw_info))
+        .route("/lnurlw/{username}", get(withdraw))
+        .route("/health", get(get_health))
+        .route("/info", get(get_info))
+        .with_state(state)
+}
 
-    let listener = tokio::net::TcpListener::bind(&config.server.listen_addr).await?;
-    tracing::info!("listening on {}", config.server.listen_addr);
+/// Serves plain HTTP on a TCP address or a unix socket, or HTTPS if `tls` is configured for it.
+/// TLS and the unix socket permissions are taken from `server_config`, for every `listen_addr`.
+async fn serve(
+    app: Router,
+    listen_addr: &str,
+    server_config: &ServerConfig,
+    shutdown: ShutdownListener,
+) -> Result<()> {
+    match server_config.tls_for(listen_addr) {
+        Some(tls) => {
+            let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to load TLS certificate {} and key {}",
+                        tls.cert_path, tls.key_path
+                    )
+                })?;
+            let addr: SocketAddr = listen_addr.parse().with_context(|| {
+                format!(
+                    "listen_addr {} must be an IP address and port when TLS is enabled",
+                    listen_addr
+                )
+            })?;
 
-    axum::serve(listener, app).await?;
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown.wait().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            tracing::info!("listening on {} (https)", addr);
+            axum_server::bind_rustls(addr, rustls_config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        None if listen_addr.starts_with('/') => {
+            let path = listen_addr;
+            // a socket left over by a previous run would make bind fail.
+            if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("failed to bind unix socket {}", path))?;
+            if let Some(mode) = server_config.unix_socket_mode()? {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+            tracing::info!("listening on unix socket {}", path);
+            if server_config.ip_rate_limit.is_some() && !server_config.trust_proxy {
+                tracing::warn!(
+                    "unix socket {} has no client IP without trust_proxy, its requests are not rate limited by IP.",
+                    path
+                );
+            }
+            axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(shutdown.wait())
+                .await?;
+        }
+        None => {
+            let listener = tokio::net::TcpListener::bind(listen_addr).await?;
+            tracing::info!("listening on {}", listen_addr);
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown.wait())
+            .await?;
+        }
+    }
     Ok(())
 }
 
+/// The pay info at `/.well-known/lnurlp/{username}`, or the QR code of the address at
+/// `/.well-known/lnurlp/{username}.png`. The latter can't be routed on its own, since it shares its
+/// path segment with the username. A user actually named `*.png` takes precedence.
+async fn get_lnurlp_info_or_address_qr(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    if let Some(address_username) = username.strip_suffix(".png")
+        && state.load().any_user(&username).is_none()
+    {
+        let address_username = Path(address_username.to_string());
+        return get_address_qr(State(state), address_username, headers).await;
+    }
+    let info = get_lnurlp_info(State(state), Path(username), headers).await?;
+    Ok(info.into_response())
+}
+
+#[tracing::instrument(name = "get_lnurlp_info", skip_all, fields(username = %username))]
 async fn get_lnurlp_info(
-    State(state): State<Arc<AppState>>,
+    State(state): State<SharedState>,
     Path(username): Path<String>,
+    headers: HeaderMap,
 ) -> Result<Json<LnUrlPayInfo>, HttpError> {
-    if !state.users.contains_key(&username) {
-        let e = Lud06Error::new(format!("user {} not found", username));
-        return Err(HttpError::new(StatusCode::BAD_REQUEST, e));
-    }
+    let state = state.load_full();
+    let (name, user) = state.payee(&username)?;
+    LNURLP_INFO_REQUESTS.with_label_values(&[&user.name]).inc();
 
-    let metadata = LnUrlPayInfo {
-        callback: format!("https://{}/lnurlp/{}", state.domain, username),
-        max_sendable: 100_000_000_000, // 1 bitcoin
-        min_sendable: 1_000,           // 1 sat
-        metadata: generate_metadata(&state, &username)?,
-        tag: "payRequest",
-    };
+    let callback = format!(
+        "https://{}/lnurlp/{}",
+        callback_host(&state, &headers),
+        name
+    );
+    let metadata = LnUrlPayInfo::new(&state, &name, &user, callback).await?;
     Ok(Json(metadata))
 }
 
-fn generate_metadata(state: &AppState, username: &str) -> Result<String> {
+fn is_hex_pubkey(username: &str) -> bool {
+    username.len() == 64 && username.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// LUD-16 only allows these characters in usernames.
+fn is_lud16_username(username: &str) -> bool {
+    !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+'))
+}
+
+/// The host of the callback url. Behind a reverse proxy, it's the host the proxy was requested with,
+/// falling back to `domain` when the header is missing or malformed.
+fn callback_host<'a>(state: &'a AppState, headers: &'a HeaderMap) -> &'a str {
+    if !state.use_forwarded_host {
+        return &state.domain;
+    }
+    headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|value| value.to_str().ok())
+        // a proxy chain may append several hosts, the first one is what the client asked for.
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|host| is_valid_host(host))
+        .unwrap_or(&state.domain)
+}
+
+// hostname or IP, with an optional port. Anything else could be used to inject into the callback url.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+// the currencies are only advertised when their exchange rates are known.
+async fn get_currencies(state: &AppState) -> Option<Vec<Currency>> {
+    let exchange_rates = state.exchange_rates.as_ref()?;
+    match exchange_rates.currencies().await {
+        Ok(currencies) => Some(currencies),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to get exchange rates.");
+            None
+        }
+    }
+}
+
+// both the info and the callback build the metadata here, so that the description_hash always matches.
+fn generate_metadata(state: &AppState, username: &str, user: &User) -> Result<String> {
+    let address = format!("{}@{}", username, state.domain);
+    let display_name = user.display_name.as_deref().unwrap_or(&address);
+    let prefix = user.invoice_description_prefix.as_deref().unwrap_or("");
+    let description = user
+        .description
+        .as_deref()
+        .unwrap_or("powered by https://github.com/yfaming/thor");
     // LUD-16 requires that there must be either a `text/identifier` or a `text/email` metadata entry.
-    let v = serde_json::json!([
-        [
-            "text/identifier".to_string(),
-            format!("{}@{}", username, state.domain)
-        ],
+    let mut v = serde_json::json!([
+        ["text/identifier".to_string(), address],
         [
             "text/plain".to_string(),
-            format!("sats for {}@{}", username, state.domain)
-        ],
-        [
-            "text/plain".to_string(),
-            "powered by https://github.com/yfaming/thor".to_string()
+            format!("{}sats for {}", prefix, display_name)
         ],
+        ["text/plain".to_string(), description.to_string()],
     ]);
+    if let (Some(avatar), Some(entries)) = (&user.avatar, v.as_array_mut()) {
+        entries.push(serde_json::json!([avatar.kind, avatar.base64]));
+    }
     let metadata_str = serde_json::to_string(&v)?;
     Ok(metadata_str)
 }
@@ -108,127 +880,642 @@ struct LnUrlPayInfo {
     min_sendable: u64, // msat
     metadata: String,
     tag: &'static str, // "payRequest"
+    // LUD-12
+    #[serde(rename = "commentAllowed", skip_serializing_if = "is_zero")]
+    comment_allowed: u64,
+    // LUD-18
+    #[serde(rename = "payerData", skip_serializing_if = "Option::is_none")]
+    payer_data: Option<PayerDataConfig>,
+    // LUD-21
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currencies: Option<Vec<Currency>>,
+    // NIP-57
+    #[serde(rename = "allowsNostr", skip_serializing_if = "is_false")]
+    allows_nostr: bool,
+    #[serde(rename = "nostrPubkey", skip_serializing_if = "Option::is_none")]
+    nostr_pubkey: Option<String>,
+    // LUD-11
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disposable: Option<bool>,
+}
+
+impl LnUrlPayInfo {
+    /// `name` is the one the metadata is made for, see `AppState::payee`.
+    async fn new(
+        state: &AppState,
+        name: &str,
+        user: &User,
+        callback: String,
+    ) -> Result<LnUrlPayInfo> {
+        Ok(LnUrlPayInfo {
+            callback,
+            max_sendable: user.max_sendable,
+            min_sendable: user.min_sendable,
+            metadata: generate_metadata(state, name, user)?,
+            tag: "payRequest",
+            comment_allowed: user.comment_allowed,
+            payer_data: user.payer_data.clone(),
+            currencies: get_currencies(state).await,
+            allows_nostr: user.zapper.is_some(),
+            nostr_pubkey: user.zapper.as_ref().map(|zapper| zapper.pubkey()),
+            disposable: None,
+        })
+    }
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
 }
 
 async fn create_invoice(
-    State(state): State<Arc<AppState>>,
+    State(state): State<SharedState>,
     Path(username): Path<String>,
-    Query(amount): Query<Amount>,
+    Query(params): Query<InvoiceParams>,
+    request_id: Option<Extension<RequestId>>,
+    client_ip: Option<Extension<ClientIp>>,
 ) -> Result<Json<InvoiceResponse>, HttpError> {
-    if amount.amount == 0 {
-        let e = Lud06Error::new("amount must > 0".to_string());
-        return Err(HttpError::new(StatusCode::BAD_REQUEST, e));
-    }
+    // the id is set by the `request_id` middleware, which is only added by `run_http_server`.
+    let request_id = match request_id {
+        Some(Extension(RequestId(request_id))) => request_id,
+        None => Uuid::new_v4().to_string(),
+    };
+    let span = tracing::info_span!(
+        "create_invoice",
+        username,
+        amount_msat = params.amount,
+        invoice_bolt11 = tracing::field::Empty,
+    );
+    let client_ip = client_ip.map(|Extension(ClientIp(ip))| ip);
+    do_create_invoice(state, username, params, client_ip)
+        .instrument(span)
+        .await
+        .map_err(|e| e.with_request_id(request_id))
+}
 
-    let creators = match state.users.get(&username) {
-        Some(creators) => {
-            let mut creators: Vec<_> = creators.iter().map(|creator| creator.as_ref()).collect();
-            creators.shuffle(&mut rand::rng());
-            creators
+async fn do_create_invoice(
+    state: SharedState,
+    username: String,
+    params: InvoiceParams,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<InvoiceResponse>, HttpError> {
+    // keep a consistent snapshot for the whole request, even if the config is reloaded meanwhile.
+    let state = state.load_full();
+    // LUD-06: the amount is in msat, unless a LUD-21 currency is given.
+    let amount = match params.amount {
+        Some(0) => {
+            let e = Lud06Error::new("amount must > 0".to_string())
+                .with_code(ErrorCode::AmountOutOfRange);
+            return Err(HttpError::new(StatusCode::BAD_REQUEST, e));
         }
+        Some(amount) => amount,
         None => {
-            let e = Lud06Error::new(format!("user {} not found", username));
+            let e = Lud06Error::new("amount is required, in msat".to_string())
+                .with_code(ErrorCode::AmountMissing);
             return Err(HttpError::new(StatusCode::BAD_REQUEST, e));
         }
     };
 
-    // LUD-06 requires that we use the hash of the metadata as `description_hash` of invoice.
-    let metadata = generate_metadata(&state, &username)?;
-    let description_hash = format!("{}", Sha256::hash(metadata.as_bytes()));
+    let (name, user) = state.payee(&username)?;
+    let username = user.name.clone();
 
-    // attempt at most 3 times
-    let mut last_err = None;
-    for creator in creators.iter().take(3) {
-        match creator
-            .create_invoice(amount.amount, &description_hash)
-            .await
-        {
-            Ok(invoice) => {
-                tracing::info!(username = username, invoice = invoice, "invoice created.");
-                return Ok(Json(InvoiceResponse {
-                    pr: invoice,
-                    routes: vec![],
-                }));
+    // LUD-21: with a currency, the amount is in the smallest unit of that currency.
+    let amount_msat = match &params.currency {
+        Some(currency) => {
+            let exchange_rates = state.exchange_rates.as_ref().ok_or_else(|| {
+                BadRequest::UnsupportedCurrency("currencies are not supported".to_string())
+            })?;
+            let currency = exchange_rates.currency(currency).ok_or_else(|| {
+                BadRequest::UnsupportedCurrency(format!("currency {} is not supported", currency))
+            })?;
+            // the provider failed, not the payer.
+            exchange_rates
+                .to_msat(amount, currency)
+                .await
+                .map_err(|e| {
+                    let e = Lud06Error::new(format!("failed to convert the amount: {}", e))
+                        .with_code(ErrorCode::ExchangeRateUnavailable);
+                    HttpError::new(StatusCode::BAD_GATEWAY, e)
+                })?
+        }
+        None => amount,
+    };
+
+    if amount_msat < user.min_sendable || amount_msat > user.max_sendable {
+        return Err(BadRequest::AmountOutOfRange {
+            min: user.min_sendable,
+            max: user.max_sendable,
+        }
+        .into());
+    }
+
+    // LUD-12
+    let comment = params.comment.as_deref();
+    if let Some(comment) = comment
+        && comment.chars().count() as u64 > user.comment_allowed
+    {
+        return Err(BadRequest::CommentTooLong {
+            max: user.comment_allowed,
+        }
+        .into());
+    }
+
+    if let Some(quota) = user.rate_limit
+        && let Err(retry_after) = state.user_rate_limiters.check(&user.name, quota)
+    {
+        tracing::warn!(user = username, "rate limit exceeded.");
+        return Err(HttpError::too_many_requests(retry_after));
+    }
+
+    // held until the invoice is created, so that a user flooded with requests can't tie up all
+    // the backend connections.
+    let _permit = match &user.concurrency_limit {
+        Some(concurrency_limit) => Some(concurrency_limit.try_acquire().map_err(|_| {
+            tracing::warn!(user = username, "too many concurrent requests.");
+            let e = Lud06Error::new("too many concurrent requests, please retry later".to_string())
+                .with_code(ErrorCode::RateLimitExceeded);
+            HttpError::new(StatusCode::TOO_MANY_REQUESTS, e)
+        })?),
+        None => None,
+    };
+
+    // NIP-57
+    let zap = match &params.nostr {
+        Some(zap_request_str) => {
+            let zapper = user.zapper.clone().ok_or_else(|| {
+                BadRequest::InvalidZapRequest(format!("user {} doesn't accept zaps", username))
+            })?;
+            let zap_request = parse_zap_request(zap_request_str, amount_msat)
+                .map_err(|e| BadRequest::InvalidZapRequest(e.to_string()))?;
+            // held by the poller of the invoice, until it's paid or expired.
+            let poller = Arc::clone(&state.zap_pollers)
+                .try_acquire_owned()
+                .map_err(|_| {
+                    tracing::warn!(user = username, "too many pending zaps.");
+                    let e =
+                        Lud06Error::new("too many pending zaps, please retry later".to_string())
+                            .with_code(ErrorCode::RateLimitExceeded);
+                    HttpError::new(StatusCode::TOO_MANY_REQUESTS, e)
+                })?;
+            Some((zapper, zap_request, poller))
+        }
+        None => None,
+    };
+
+    // only NWC can tell when a zapped invoice is paid.
+    let mut creators =
+        ordered_creators(&user, |creator| zap.is_none() || creator.backend() == "nwc");
+    let max_attempts = user
+        .invoice_max_attempts
+        .unwrap_or(DEFAULT_INVOICE_MAX_ATTEMPTS)
+        .min(creators.len());
+    creators.truncate(max_attempts);
+
+    let payer_data = match &params.payer_data {
+        Some(payer_data_str) => serde_json::from_str(payer_data_str)
+            .map_err(|e| BadRequest::InvalidPayerData(e.to_string()))?,
+        None => PayerData::default(),
+    };
+    user.payer_data
+        .as_ref()
+        .unwrap_or(&PayerDataConfig::default())
+        .validate(&payer_data)
+        .map_err(|e| BadRequest::InvalidPayerData(e.to_string()))?;
+
+    // LUD-18 requires that the payer data is hashed together with the metadata, exactly as it was sent.
+    // NIP-57 requires the zap request instead.
+    let description = match &params.nostr {
+        Some(zap_request_str) => zap_request_str.clone(),
+        None => {
+            let mut description = generate_metadata(&state, &name, &user)?;
+            if let Some(payer_data_str) = &params.payer_data {
+                description.push_str(payer_data_str);
             }
-            Err(e) => {
-                tracing::warn!(user = username, error = %e, "failed to create invoice.");
-                last_err = Some(e);
+            description
+        }
+    };
+
+    // the description of the invoice must be the same, so only plain requests are deduplicated.
+    // and only for the same client, since once it's paid, it can't be paid again by another.
+    let dedup = user
+        .invoice_dedup_ttl
+        .filter(|_| comment.is_none() && params.payer_data.is_none() && zap.is_none())
+        .zip(client_ip);
+    if let Some((_, client_ip)) = dedup
+        && let Some(invoice) = state.recent_invoices.get(&username, amount_msat, client_ip)
+    {
+        tracing::Span::current().record("invoice_bolt11", invoice.as_str());
+        tracing::info!(
+            username = username,
+            amount_msat = amount_msat,
+            "recent invoice returned again."
+        );
+        return Ok(Json(InvoiceResponse {
+            pr: invoice,
+            routes: vec![],
+            success_action: user.success_action.clone(),
+        }));
+    }
+
+    // reserved upfront so that concurrent requests can't exceed the cap together.
+    // given back if this future is dropped before the invoice is created.
+    let reservation = match user.daily_invoice_cap {
+        Some(cap) => match state.daily_totals.try_reserve(&username, amount_msat, cap) {
+            Some(reservation) => Some(reservation),
+            None => {
+                tracing::warn!(user = username, "daily invoice cap reached.");
+                let e =
+                    Lud06Error::new("daily invoice cap reached, please retry tomorrow".to_string())
+                        .with_code(ErrorCode::DailyCapReached);
+                return Err(HttpError::new(StatusCode::TOO_MANY_REQUESTS, e));
             }
-        };
+        },
+        None => None,
+    };
+
+    // each creator is retried with backoff according to the retry policy, before moving on to the next.
+    let observed = creators
+        .iter()
+        .map(|creator| {
+            Arc::new(ObservedInvoiceCreator {
+                inner: Arc::clone(creator),
+                state: Arc::clone(&state),
+                username: username.clone(),
+            }) as Arc<dyn InvoiceCreator>
+        })
+        .collect();
+    let retryable = RetryableInvoiceCreator::new(observed, user.retry_policy.clone());
+    let res = retryable
+        .create_invoice(
+            amount_msat,
+            &description,
+            comment,
+            Some(user.invoice_expiry),
+        )
+        .await;
+    let invoice = match res {
+        Ok(invoice) => invoice,
+        Err(e) => {
+            // a single backend failing is only a warning, see `RetryableInvoiceCreator`. This is
+            // the event to alert on.
+            tracing::error!(
+                username = username,
+                backends_tried = creators.len(),
+                last_error = %e,
+                "all backends exhausted."
+            );
+            return Err(match e.downcast::<nwc::Error>() {
+                Ok(e) => e.into(),
+                Err(e) => HttpError::from(e).with_code(ErrorCode::BackendUnavailable),
+            });
+        }
+    };
+
+    if let Some(reservation) = reservation {
+        reservation.commit();
+    }
+    if let Some((ttl, client_ip)) = dedup {
+        state
+            .recent_invoices
+            .insert(&username, amount_msat, client_ip, &invoice, ttl);
+    }
+    INVOICES_CREATED.with_label_values(&[&username]).inc();
+    tracing::Span::current().record("invoice_bolt11", invoice.as_str());
+    // informational only, the invoice is in msat whatever the display currency.
+    let display_amount = state
+        .exchange_rates
+        .as_ref()
+        .and_then(|exchange_rates| exchange_rates.display_amount(amount_msat));
+    tracing::info!(
+        username = username,
+        amount_msat = amount_msat,
+        display_amount = display_amount,
+        invoice = invoice,
+        "invoice created."
+    );
+    let event = InvoiceCreated::now(&username, amount_msat, &invoice);
+    state.last_invoices.record(event.clone());
+    if let Some(webhook) = &user.webhook {
+        tokio::spawn(Arc::clone(webhook).notify(event));
+    }
+    // the invoice is looked up on the creator that made it, without the observation around it.
+    if let Some((zapper, zap_request, poller)) = zap
+        && let Some(i) = retryable.succeeded()
+    {
+        let publish = zapper.publish_when_paid(
+            Arc::clone(creators[i]),
+            invoice.clone(),
+            zap_request,
+            Duration::from_secs(user.invoice_expiry),
+        );
+        tokio::spawn(async move {
+            publish.await;
+            drop(poller);
+        });
+    }
+    Ok(Json(InvoiceResponse {
+        pr: invoice,
+        routes: vec![],
+        success_action: user.success_action.clone(),
+    }))
+}
+
+/// Records the metrics, the backend stats and the invoice log of every invoice creation attempt.
+struct ObservedInvoiceCreator {
+    inner: Arc<dyn InvoiceCreator>,
+    state: Arc<AppState>,
+    username: String,
+}
+
+#[async_trait::async_trait]
+impl InvoiceCreator for ObservedInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        self.inner.backend()
     }
 
-    match last_err {
-        Some(e) => {
-            tracing::error!(user = username, error = %e, "failed to create invoice. All attempts failed.");
-            Err(e.into())
+    fn endpoint(&self) -> String {
+        self.inner.endpoint()
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.inner.check_health().await
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        comment: Option<&str>,
+        expiry: Option<u64>,
+    ) -> Result<String> {
+        let backend = self.inner.backend();
+        let timer = NWC_ATTEMPT_DURATION
+            .with_label_values(&[backend])
+            .start_timer();
+        let res = self
+            .inner
+            .create_invoice(amount_msat, description, comment, expiry)
+            .await;
+        let latency = Duration::from_secs_f64(timer.stop_and_record());
+        self.state
+            .backend_latencies
+            .record(backend, &self.inner.endpoint(), latency);
+        status::record(
+            &self.state.backend_stats,
+            backend,
+            self.inner.endpoint(),
+            res.is_ok(),
+        );
+        if let Some(invoice_log) = &self.state.invoice_log {
+            let error_msg;
+            let attempt = InvoiceAttempt {
+                username: &self.username,
+                amount_msat,
+                backend,
+                result: match &res {
+                    Ok(invoice) => Ok(invoice),
+                    Err(e) => {
+                        error_msg = e.to_string();
+                        Err(&error_msg)
+                    }
+                },
+            };
+            if let Err(e) = invoice_log.record(&attempt).await {
+                tracing::error!(error = %e, "failed to record invoice attempt.");
+            }
+        }
+        if res.is_err() {
+            INVOICE_ERRORS
+                .with_label_values(&[&self.username, backend])
+                .inc();
         }
-        None => unreachable!(),
+        res
     }
+
+    async fn lookup_invoice(&self, invoice: &str) -> Result<InvoiceStatus> {
+        self.inner.lookup_invoice(invoice).await
+    }
+}
+
+/// The primary creators in random order to spread the load, followed by the fallback ones in random order.
+fn ordered_creators(
+    user: &User,
+    filter: impl Fn(&Arc<dyn InvoiceCreator>) -> bool,
+) -> Vec<&Arc<dyn InvoiceCreator>> {
+    let (primaries, fallbacks) = user.invoice_creators.split_at(user.primary_creators);
+    let mut rng = rand::rng();
+    let mut creators = vec![];
+    for group in [primaries, fallbacks] {
+        let mut group: Vec<_> = group.iter().filter(|creator| filter(creator)).collect();
+        group.shuffle(&mut rng);
+        creators.extend(group);
+    }
+    creators
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InvoiceResponse {
     pr: String,          // invoice
     routes: Vec<String>, // empty
+    // LUD-09
+    #[serde(rename = "successAction", skip_serializing_if = "Option::is_none")]
+    success_action: Option<SuccessAction>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Amount {
-    amount: u64,
+#[derive(Debug, Default, Deserialize)]
+struct InvoiceParams {
+    // optional, so that a missing amount is reported as a LUD-06 error rather than by axum.
+    amount: Option<u64>,
+    comment: Option<String>, // LUD-12
+    #[serde(rename = "payerData")]
+    payer_data: Option<String>, // LUD-18, JSON encoded
+    currency: Option<String>, // LUD-21
+    nostr: Option<String>,   // NIP-57, JSON encoded zap request
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::invoice_creator::BackendInvoiceCreatorFactory;
+    use crate::test_utils::{MockFailCreator, MockInvoiceCreator, app_state, app_state_with_user};
     use axum::response::IntoResponse;
     use std::collections::HashMap;
 
-    struct DummyCreator {
-        result: std::result::Result<String, String>,
+    #[tokio::test]
+    async fn get_lnurlp_info_unknown_user_returns_not_found() {
+        let state = Arc::new(ArcSwap::from_pointee(app_state(HashMap::new())));
+        let res = get_lnurlp_info(State(state), Path("alice".to_string()), HeaderMap::new()).await;
+        assert!(res.is_err());
+        let response = res.unwrap_err().into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
-    #[async_trait::async_trait]
-    impl InvoiceCreator for DummyCreator {
-        async fn create_invoice(
-            &self,
-            _amount_msat: u64,
-            _description_hash: &str,
-        ) -> Result<String> {
-            match &self.result {
-                Ok(invoice) => Ok(invoice.clone()),
-                Err(msg) => Err(anyhow::anyhow!("{msg}")),
-            }
-        }
+    #[tokio::test]
+    async fn get_lnurlp_info_resolves_aliases_to_name() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = app_state_with_user("alice", vec![creator]);
+        let alice = state.user("alice").unwrap();
+        state.users.write().unwrap().insert("a".to_string(), alice);
+        let state = Arc::new(ArcSwap::from_pointee(state));
+
+        let Json(info) = get_lnurlp_info(State(state), Path("a".to_string()), HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(info.callback, "https://example.com/lnurlp/alice");
+        assert!(info.metadata.contains("alice@example.com"));
+        assert!(!info.metadata.contains("\"a@example.com\""));
     }
 
-    fn create_app_state(user: &str, creators: Vec<Box<dyn InvoiceCreator>>) -> AppState {
-        let mut users = HashMap::new();
-        users.insert(user.to_string(), creators);
-        AppState {
-            domain: "example.com".to_string(),
-            users,
+    #[tokio::test]
+    async fn usernames_are_case_insensitive() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+
+        let Json(info) = get_lnurlp_info(
+            State(state.clone()),
+            Path("Alice".to_string()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.callback, "https://example.com/lnurlp/alice");
+        let res = create_invoice(
+            State(state),
+            Path("ALICE".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unknown_usernames_are_paid_to_catch_all_user() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let mut state = app_state_with_user("alice", vec![creator]);
+        state.catch_all_user = Some("alice".to_string());
+        let state = Arc::new(ArcSwap::from_pointee(state));
+
+        let Json(info) = get_lnurlp_info(
+            State(state.clone()),
+            Path("Bob".to_string()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.callback, "https://example.com/lnurlp/bob");
+        assert!(info.metadata.contains("bob@example.com"));
+
+        let params = || {
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            })
+        };
+        let res = create_invoice(
+            State(state.clone()),
+            Path("bob".to_string()),
+            params(),
+            None,
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+        let err = create_invoice(State(state), Path("b?ob".to_string()), params(), None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn disabled_users_are_not_found() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let mut state = app_state_with_user("alice", vec![creator]);
+        state.catch_all_user = Some("alice".to_string());
+        let alice = state.users.get_mut().unwrap().get_mut("alice").unwrap();
+        Arc::get_mut(alice).unwrap().enabled = false;
+        let state = Arc::new(ArcSwap::from_pointee(state));
+
+        let err = get_lnurlp_info(
+            State(state.clone()),
+            Path("alice".to_string()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+        for username in ["alice", "bob"] {
+            let err = create_invoice(
+                State(state.clone()),
+                Path(username.to_string()),
+                Query(InvoiceParams {
+                    amount: Some(1500),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
         }
     }
 
     #[tokio::test]
-    async fn get_lnurlp_info_unknown_user_returns_bad_request() {
-        let state = Arc::new(AppState {
-            domain: "example.com".to_string(),
-            users: HashMap::new(),
-        });
-        let res = get_lnurlp_info(State(state), Path("alice".to_string())).await;
-        assert!(res.is_err());
-        let response = res.unwrap_err().into_response();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    async fn nostr_pubkeys_are_paid_to_their_user() {
+        let pubkey = "b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4";
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = app_state_with_user("alice", vec![creator]);
+        let alice = state.user("alice").unwrap();
+        state
+            .nostr_pubkeys
+            .write()
+            .unwrap()
+            .insert(pubkey.to_string(), alice);
+        let state = Arc::new(ArcSwap::from_pointee(state));
+
+        let Json(info) = get_lnurlp_info(
+            State(state.clone()),
+            Path(pubkey.to_uppercase()),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.callback, "https://example.com/lnurlp/alice");
+
+        let err = get_lnurlp_info(State(state), Path("f".repeat(64)), HeaderMap::new())
+            .await
+            .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(
+            body["reason"]
+                .as_str()
+                .unwrap()
+                .starts_with("no user has nostr pubkey")
+        );
     }
 
     #[test]
     fn generate_metadata_includes_identifier() -> Result<()> {
-        let creator = Box::new(DummyCreator {
-            result: Ok("lnbc1test".to_string()),
-        });
-        let state = create_app_state("alice", vec![creator]);
-        let metadata = generate_metadata(&state, "alice")?;
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = app_state_with_user("alice", vec![creator]);
+        let user = state.user("alice").unwrap();
+        let metadata = generate_metadata(&state, "alice", &user)?;
         let parsed: Vec<Vec<String>> = serde_json::from_str(&metadata).unwrap();
         assert!(parsed.iter().any(|entry| {
             entry.len() == 2 && entry[0] == "text/identifier" && entry[1] == "alice@example.com"
@@ -238,14 +1525,20 @@ mod tests {
 
     #[tokio::test]
     async fn create_invoice_rejects_zero_amount() {
-        let creator = Box::new(DummyCreator {
-            result: Ok("lnbc1test".to_string()),
-        });
-        let state = Arc::new(create_app_state("alice", vec![creator]));
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
         let err = create_invoice(
             State(state),
             Path("alice".to_string()),
-            Query(Amount { amount: 0 }),
+            Query(InvoiceParams {
+                amount: Some(0),
+                ..Default::default()
+            }),
+            None,
+            None,
         )
         .await
         .unwrap_err();
@@ -255,18 +1548,913 @@ mod tests {
 
     #[tokio::test]
     async fn create_invoice_returns_invoice() {
-        let creator = Box::new(DummyCreator {
-            result: Ok("lnbc1test".to_string()),
-        });
-        let state = Arc::new(create_app_state("alice", vec![creator]));
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
         let response = create_invoice(
             State(state),
             Path("alice".to_string()),
-            Query(Amount { amount: 1500 }),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
         )
         .await
         .unwrap();
         assert_eq!(response.0.pr, "lnbc1test");
         assert!(response.0.routes.is_empty());
+        assert_eq!(
+            response.0.success_action,
+            Some(SuccessAction::Message {
+                message: "thanks".to_string()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn create_invoice_accepts_comment_within_limit() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let response = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                comment: Some("thanks!".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0.pr, "lnbc1test");
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_too_long_comment() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                comment: Some("this comment is too long".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_amount_out_of_range() {
+        for amount in [999, 1_000_001] {
+            let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+            let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+                "alice",
+                vec![creator],
+            )));
+            let err = create_invoice(
+                State(state),
+                Path("alice".to_string()),
+                Query(InvoiceParams {
+                    amount: Some(amount),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+            let response = err.into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_requests_over_rate_limit() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 2));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        for _ in 0..2 {
+            let res = create_invoice(
+                State(state.clone()),
+                Path("alice".to_string()),
+                Query(InvoiceParams {
+                    amount: Some(1500),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .await;
+            assert!(res.is_ok());
+        }
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    /// Blocks every invoice until released.
+    struct BlockedCreator {
+        release: Arc<tokio::sync::Notify>,
+    }
+
+    #[async_trait::async_trait]
+    impl InvoiceCreator for BlockedCreator {
+        fn backend(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn check_health(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn create_invoice(
+            &self,
+            _amount_msat: u64,
+            _description: &str,
+            _comment: Option<&str>,
+            _expiry: Option<u64>,
+        ) -> Result<String> {
+            self.release.notified().await;
+            Ok("lnbc1test".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_requests_over_concurrency_limit() {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let creator = Box::new(BlockedCreator {
+            release: release.clone(),
+        });
+        let mut state = app_state_with_user("alice", vec![creator]);
+        let user = state.users.get_mut().unwrap().get_mut("alice").unwrap();
+        Arc::get_mut(user).unwrap().concurrency_limit = Some(Semaphore::new(1));
+        let state = Arc::new(ArcSwap::from_pointee(state));
+        let request = || {
+            create_invoice(
+                State(state.clone()),
+                Path("alice".to_string()),
+                Query(InvoiceParams {
+                    amount: Some(1500),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+        };
+
+        let first = tokio::spawn(request());
+        while state.load().users.read().unwrap()["alice"]
+            .concurrency_limit
+            .as_ref()
+            .unwrap()
+            .available_permits()
+            > 0
+        {
+            tokio::task::yield_now().await;
+        }
+        let err = request().await.unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::TOO_MANY_REQUESTS);
+
+        release.notify_one();
+        assert!(first.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn create_invoice_error_includes_request_id() {
+        let creator = Box::new(MockFailCreator);
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "ERROR");
+        assert!(body["requestId"].as_str().is_some_and(|id| !id.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn create_invoice_error_uses_request_id_header() {
+        use tower::ServiceExt;
+        let creator = Box::new(MockFailCreator);
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let app = router(state).layer(middleware::from_fn(request_id));
+        let request = axum::http::Request::builder()
+            .uri("/lnurlp/alice?amount=1500")
+            .header("x-request-id", "support-42")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()["x-request-id"], "support-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["requestId"], "support-42");
+    }
+
+    #[tokio::test]
+    async fn get_health_reports_unreachable_backends() {
+        let ok_creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![ok_creator],
+        )));
+        let (status_code, _) = get_health(State(state)).await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let failing_creator = Box::new(MockFailCreator);
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![failing_creator],
+        )));
+        let (status_code, _) = get_health(State(state)).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn get_health_leaves_out_disabled_users() {
+        let mut state = app_state_with_user("alice", vec![Box::new(MockFailCreator)]);
+        let alice = state.users.get_mut().unwrap().get_mut("alice").unwrap();
+        Arc::get_mut(alice).unwrap().enabled = false;
+        let state = Arc::new(ArcSwap::from_pointee(state));
+        let (status_code, Json(res)) = get_health(State(state)).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(
+            serde_json::to_value(res).unwrap()["users"],
+            serde_json::json!({})
+        );
+    }
+
+    #[tokio::test]
+    async fn get_health_is_cached() {
+        let ok_creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![ok_creator],
+        )));
+        let (status_code, _) = get_health(State(state.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let failing = app_state_with_user("alice", vec![Box::new(MockFailCreator)]);
+        let failing_user = failing.user("alice").unwrap();
+        state
+            .load()
+            .users
+            .write()
+            .unwrap()
+            .insert("alice".to_string(), failing_user);
+        let (status_code, _) = get_health(State(state)).await;
+        assert_eq!(status_code, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn get_usernames_only_when_enabled() {
+        let state = |list_users| {
+            let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+            let mut state = app_state_with_user("alice", vec![creator]);
+            state.list_users = list_users;
+            Arc::new(ArcSwap::from_pointee(state))
+        };
+        let e = get_usernames(State(state(false))).await.unwrap_err();
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+
+        let Json(usernames) = get_usernames(State(state(true))).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(usernames).unwrap()["users"],
+            serde_json::json!(["alice"])
+        );
+    }
+
+    #[tokio::test]
+    async fn warm_up_reports_unreachable_backends() {
+        let creators: Vec<Box<dyn InvoiceCreator>> = vec![
+            Box::new(MockInvoiceCreator::repeat("lnbc1test", 1)),
+            Box::new(MockFailCreator),
+        ];
+        let state = Arc::new(app_state_with_user("alice", creators));
+        let failures = warm_up(&[state]).await;
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].starts_with("alice@"));
+        assert!(failures[0].ends_with("backend down"));
+    }
+
+    #[tokio::test]
+    async fn create_invoice_validates_payer_data() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let res = create_invoice(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                payer_data: Some(r#"{"name":"bob"}"#.to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                payer_data: Some(r#"{"email":"bob@example.com"}"#.to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn create_invoice_converts_fiat_amount() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        // 1 cent = 10,000 msat
+        let res = create_invoice(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(10),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+
+        // 1,000 USD is above max_sendable
+        let err = create_invoice(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(100_000),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(10),
+                currency: Some("EUR".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            err.body().code(),
+            Some(ErrorCode::UnsupportedCurrency as u16)
+        );
+    }
+
+    #[tokio::test]
+    async fn create_invoice_fails_upstream_without_exchange_rate() {
+        let mut state = app_state_with_user("alice", vec![]);
+        state.exchange_rates = Some(ExchangeRates::with_prices(
+            vec![crate::exchange_rate::tests::usd()],
+            HashMap::new(),
+        ));
+        let err = create_invoice(
+            State(Arc::new(ArcSwap::from_pointee(state))),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(10),
+                currency: Some("USD".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            err.body().code(),
+            Some(ErrorCode::ExchangeRateUnavailable as u16)
+        );
+    }
+
+    #[test]
+    fn callback_host_uses_forwarded_host_when_enabled() {
+        let mut state = app_state_with_user("alice", vec![]);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-host", "pay.example.org".parse().unwrap());
+        headers.insert(header::HOST, "127.0.0.1:1405".parse().unwrap());
+        assert_eq!(callback_host(&state, &headers), "example.com");
+
+        state.use_forwarded_host = true;
+        assert_eq!(callback_host(&state, &headers), "pay.example.org");
+
+        headers.remove("x-forwarded-host");
+        assert_eq!(callback_host(&state, &headers), "127.0.0.1:1405");
+
+        headers.insert(header::HOST, "evil.com/x?".parse().unwrap());
+        assert_eq!(callback_host(&state, &headers), "example.com");
+
+        // trusting the proxy with the client IP doesn't trust it with the host.
+        let factory = Arc::new(BackendInvoiceCreatorFactory);
+        let state = AppState::new(&config("trust_proxy = true"), factory.clone()).unwrap();
+        headers.insert("x-forwarded-host", "pay.example.org".parse().unwrap());
+        assert_eq!(callback_host(&state, &headers), "example.com");
+        let state = AppState::new(&config("use_forwarded_host = true"), factory).unwrap();
+        assert_eq!(callback_host(&state, &headers), "pay.example.org");
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_zap_for_user_without_zaps() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let state = Arc::new(ArcSwap::from_pointee(app_state_with_user(
+            "alice",
+            vec![creator],
+        )));
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                nostr: Some("{}".to_string()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn create_invoice_rejects_zap_beyond_pending_zaps() -> Result<()> {
+        use nostr_sdk::prelude::{EventBuilder, JsonUtil, Keys, Kind, Tag};
+
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let mut state = app_state_with_user("alice", vec![creator]);
+        let zap = crate::config::ZapConfig {
+            nostr_secret_key: Keys::generate().secret_key().to_secret_hex(),
+            relays: vec![],
+        };
+        let users = state.users.get_mut().unwrap();
+        Arc::get_mut(users.get_mut("alice").unwrap())
+            .unwrap()
+            .zapper = Some(Arc::new(Zapper::new(&zap)?));
+        state.zap_pollers = Arc::new(Semaphore::new(0));
+        let zap_request = EventBuilder::new(Kind::ZapRequest, "")
+            .tags([
+                Tag::public_key(Keys::generate().public_key()),
+                Tag::parse(["relays", "wss://relay.damus.io"])?,
+            ])
+            .sign_with_keys(&Keys::generate())?;
+
+        let err = create_invoice(
+            State(Arc::new(ArcSwap::from_pointee(state))),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                nostr: Some(zap_request.as_json()),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            err.body().reason(),
+            "too many pending zaps, please retry later"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn generate_metadata_uses_custom_display_name_and_description() -> Result<()> {
+        let state = app_state_with_user("alice", vec![]);
+        let mut user = User::new(
+            &serde_json::from_value(serde_json::json!({
+                "name": "alice",
+                "cln_rest": {"url": "https://127.0.0.1:3010", "rune": "rune"},
+                "display_name": "Alice",
+                "description": "tips for my podcast",
+            }))?,
+            0,
+            &BackendInvoiceCreatorFactory,
+        )?;
+        let metadata = generate_metadata(&state, "alice", &user)?;
+        let parsed: Vec<Vec<String>> = serde_json::from_str(&metadata)?;
+        assert_eq!(parsed[0], vec!["text/identifier", "alice@example.com"]);
+        assert_eq!(parsed[1], vec!["text/plain", "sats for Alice"]);
+        assert_eq!(parsed[2], vec!["text/plain", "tips for my podcast"]);
+
+        user.display_name = None;
+        user.description = None;
+        let metadata = generate_metadata(&state, "alice", &user)?;
+        let parsed: Vec<Vec<String>> = serde_json::from_str(&metadata)?;
+        assert_eq!(parsed[1], vec!["text/plain", "sats for alice@example.com"]);
+
+        user.invoice_description_prefix = Some("[acme] ".to_string());
+        let metadata = generate_metadata(&state, "alice", &user)?;
+        let parsed: Vec<Vec<String>> = serde_json::from_str(&metadata)?;
+        assert_eq!(
+            parsed[1],
+            vec!["text/plain", "[acme] sats for alice@example.com"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_invoice_falls_back_after_primaries_fail() {
+        let creators: Vec<Box<dyn InvoiceCreator>> = vec![
+            Box::new(MockFailCreator),
+            Box::new(MockInvoiceCreator::repeat("lnbc1fallback", 1)),
+        ];
+        let mut state = app_state_with_user("alice", creators);
+        state
+            .users
+            .get_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|user| Arc::get_mut(user).unwrap().primary_creators = 1);
+
+        let user = state.user("alice").unwrap();
+        let ordered = ordered_creators(&user, |_| true);
+        assert!(Arc::ptr_eq(ordered[0], &user.invoice_creators[0]));
+
+        let response = create_invoice(
+            State(Arc::new(ArcSwap::from_pointee(state))),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.0.pr, "lnbc1fallback");
+    }
+
+    /// Alice has `failures` failing primary backends, and a working fallback one.
+    async fn create_invoice_with_failures(
+        failures: usize,
+        max_attempts: Option<usize>,
+    ) -> Result<Json<InvoiceResponse>, HttpError> {
+        let mut creators: Vec<Box<dyn InvoiceCreator>> = (0..failures)
+            .map(|_| Box::new(MockFailCreator) as Box<dyn InvoiceCreator>)
+            .collect();
+        creators.push(Box::new(MockInvoiceCreator::repeat("lnbc1fallback", 1)));
+        let mut state = app_state_with_user("alice", creators);
+        state
+            .users
+            .get_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|user| {
+                let user = Arc::get_mut(user).unwrap();
+                user.primary_creators = failures;
+                user.invoice_max_attempts = max_attempts;
+            });
+        create_invoice(
+            State(Arc::new(ArcSwap::from_pointee(state))),
+            Path("alice".to_string()),
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            }),
+            None,
+            None,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn create_invoice_stops_at_max_attempts() {
+        assert!(create_invoice_with_failures(1, Some(1)).await.is_err());
+        assert!(create_invoice_with_failures(1, Some(2)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn create_invoice_tries_three_backends_by_default() {
+        assert!(create_invoice_with_failures(2, None).await.is_ok());
+        let err = create_invoice_with_failures(3, None).await.unwrap_err();
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn create_invoice_enforces_daily_cap() {
+        let creator = Box::new(MockInvoiceCreator::repeat("lnbc1test", 1));
+        let mut state = app_state_with_user("alice", vec![creator]);
+        state
+            .users
+            .get_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|user| Arc::get_mut(user).unwrap().daily_invoice_cap = Some(2_000));
+        let state = Arc::new(ArcSwap::from_pointee(state));
+        let params = || {
+            Query(InvoiceParams {
+                amount: Some(1500),
+                ..Default::default()
+            })
+        };
+
+        let res = create_invoice(
+            State(state.clone()),
+            Path("alice".to_string()),
+            params(),
+            None,
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+        let err = create_invoice(
+            State(state),
+            Path("alice".to_string()),
+            params(),
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.into_response().status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn create_invoice_gives_back_cap_of_dropped_request() {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let creator = Box::new(BlockedCreator {
+            release: release.clone(),
+        });
+        let mut state = app_state_with_user("alice", vec![creator]);
+        let user = state.users.get_mut().unwrap().get_mut("alice").unwrap();
+        Arc::get_mut(user).unwrap().daily_invoice_cap = Some(2_000);
+        let state = Arc::new(ArcSwap::from_pointee(state));
+        let request = || {
+            create_invoice(
+                State(state.clone()),
+                Path("alice".to_string()),
+                Query(InvoiceParams {
+                    amount: Some(1500),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+        };
+
+        // dropped while the backend is creating the invoice, as when the request times out.
+        let res = tokio::time::timeout(Duration::from_millis(50), request()).await;
+        assert!(res.is_err());
+
+        release.notify_one();
+        assert!(request().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn create_invoice_dedups_same_amount() {
+        let creator = Box::new(MockInvoiceCreator::new(vec![
+            Ok("lnbc1first".to_string()),
+            Ok("lnbc1second".to_string()),
+            Ok("lnbc1third".to_string()),
+        ]));
+        let mut state = app_state_with_user("alice", vec![creator]);
+        state
+            .users
+            .get_mut()
+            .unwrap()
+            .values_mut()
+            .for_each(|user| {
+                let user = Arc::get_mut(user).unwrap();
+                user.rate_limit = None;
+                user.invoice_dedup_ttl = Some(Duration::from_secs(60));
+            });
+        let state = Arc::new(ArcSwap::from_pointee(state));
+        let invoice = |amount, client_ip: &'static str| {
+            let state = state.clone();
+            async move {
+                let params = InvoiceParams {
+                    amount: Some(amount),
+                    ..Default::default()
+                };
+                let client_ip = Some(Extension(ClientIp(client_ip.parse().unwrap())));
+                create_invoice(
+                    State(state),
+                    Path("alice".to_string()),
+                    Query(params),
+                    None,
+                    client_ip,
+                )
+                .await
+                .unwrap()
+                .0
+                .pr
+            }
+        };
+
+        assert_eq!(invoice(1500, "1.1.1.1").await, "lnbc1first");
+        assert_eq!(invoice(1500, "1.1.1.1").await, "lnbc1first");
+        assert_eq!(invoice(1500, "2.2.2.2").await, "lnbc1second");
+        assert_eq!(invoice(2500, "1.1.1.1").await, "lnbc1third");
+    }
+
+    fn server_config(settings: &str) -> ServerConfig {
+        config(settings).server
+    }
+
+    fn config(settings: &str) -> Config {
+        let contents = format!(
+            r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:8080"
+log_dir = "/tmp/thor"
+{settings}
+
+[[users]]
+name = "alice"
+cln_rest = {{ url = "https://127.0.0.1:3010", rune = "rune" }}
+"#
+        );
+        Config::parse(&contents, crate::config::ConfigFormat::Toml).unwrap()
+    }
+
+    async fn send(app: Router, method: &str, body: &str) -> axum::response::Response {
+        use tower::ServiceExt;
+        let request = axum::http::Request::builder()
+            .method(method)
+            .uri("/")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_limits_are_enforced() {
+        let server_config = server_config("max_request_body_bytes = 8\nrequest_timeout_ms = 50");
+        let app = Router::new().route(
+            "/",
+            get(|| tokio::time::sleep(Duration::from_secs(5)))
+                .post(|body: String| async move { body }),
+        );
+        let app = add_request_limits(app, &server_config);
+
+        let res = send(app.clone(), "POST", "12345678").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = send(app.clone(), "POST", "123456789").await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let res = send(app, "GET", "").await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn security_headers_are_added() {
+        let server_config = server_config("[server.security_headers]\nreferrer_policy = \"\"");
+        let app = Router::new().route("/", get(|| async {}));
+        let res = send(add_security_headers(app, &server_config), "GET", "").await;
+        assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(res.headers()["x-frame-options"], "DENY");
+        assert!(res.headers().contains_key("content-security-policy"));
+        // disabled, and HSTS is only sent over TLS.
+        assert!(!res.headers().contains_key("referrer-policy"));
+        assert!(!res.headers().contains_key("strict-transport-security"));
+    }
+
+    #[tokio::test]
+    async fn cors_allows_configured_origins() {
+        use tower::ServiceExt;
+        let preflight = |origin: &str| {
+            axum::http::Request::builder()
+                .method("OPTIONS")
+                .uri("/")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(&server_config("")));
+        let res = app
+            .oneshot(preflight("https://wallet.example"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_METHODS], "GET");
+        assert!(!res.headers().contains_key(header::ACCESS_CONTROL_MAX_AGE));
+
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(&server_config("cors_max_age_seconds = 3600")));
+        let res = app
+            .oneshot(preflight("https://wallet.example"))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_MAX_AGE], "3600");
+
+        let server_config = server_config("cors_allowed_origins = [\"https://wallet.example\"]");
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(cors_layer(&server_config));
+        let res = app
+            .clone()
+            .oneshot(preflight("https://wallet.example"))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://wallet.example"
+        );
+        let res = app
+            .oneshot(preflight("https://evil.example"))
+            .await
+            .unwrap();
+        assert!(
+            !res.headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
     }
 }
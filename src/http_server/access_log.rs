@@ -0,0 +1,84 @@
+use crate::rate_limit::request_client_ip;
+use axum::Router;
+use axum::extract::Request;
+use axum::response::Response;
+use std::time::Duration;
+use tower_http::trace::TraceLayer;
+use tracing::Span;
+
+/// Logs every request with its status and how long it took, in the configured log formats. Added
+/// inside `request_id`, so that the entries carry the request id as well.
+/// The client IP is taken from the forwarded headers with `trust_proxy`, like for rate limiting.
+pub fn add_access_log(app: Router, trust_proxy: bool) -> Router {
+    app.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(move |req: &Request| {
+                let path = req.uri().path();
+                // the tokens of disposable links are secrets, and stay out of the logs.
+                let path = if path.starts_with(DISPOSABLE_PREFIX) {
+                    "/lnurlp/once/..."
+                } else {
+                    path
+                };
+                let client_ip = request_client_ip(req, trust_proxy);
+                tracing::info_span!(
+                    "http_request",
+                    method = %req.method(),
+                    path,
+                    username = username(path),
+                    client_ip = client_ip.map(tracing::field::display),
+                )
+            })
+            .on_request(())
+            .on_response(|res: &Response, latency: Duration, _span: &Span| {
+                tracing::info!(
+                    status = res.status().as_u16(),
+                    latency_ms = latency.as_millis() as u64,
+                    "request completed."
+                );
+            })
+            // 5xx responses are already logged above.
+            .on_failure(()),
+    )
+}
+
+const DISPOSABLE_PREFIX: &str = "/lnurlp/once/";
+
+/// The username a request is made to, for the routes which have one.
+fn username(path: &str) -> Option<&str> {
+    const PREFIXES: [&str; 6] = [
+        "/.well-known/lnurlp/",
+        "/.well-known/lnurlw/",
+        "/lnurlp/",
+        "/lnurlw/",
+        "/lnurl/",
+        "/admin/users/",
+    ];
+    let rest = PREFIXES
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix))?;
+    if path.starts_with(DISPOSABLE_PREFIX) {
+        return None;
+    }
+    rest.split('/')
+        .next()
+        .filter(|username| !username.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn username_from_path() {
+        assert_eq!(username("/.well-known/lnurlp/alice"), Some("alice"));
+        assert_eq!(username("/.well-known/lnurlp/alice/qr"), Some("alice"));
+        assert_eq!(username("/lnurlp/alice"), Some("alice"));
+        assert_eq!(username("/lnurlw/alice"), Some("alice"));
+        assert_eq!(username("/admin/users/bob/balance"), Some("bob"));
+        assert_eq!(username("/lnurlp/once/secret-token"), None);
+        assert_eq!(username("/lnurlp/once/..."), None);
+        assert_eq!(username("/admin/users"), None);
+        assert_eq!(username("/health"), None);
+    }
+}
@@ -0,0 +1,375 @@
+use super::balance::get_balance;
+use super::last_invoice::get_last_invoice;
+use super::status::get_status;
+use super::{SharedState, User};
+use crate::config::UserConfig;
+use crate::error::{HttpError, Lud06Error};
+use crate::shutdown::ShutdownListener;
+use anyhow::Result;
+use axum::Router;
+use axum::extract::{Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Json, Response};
+use axum::routing::{delete, get};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Routes to manage users at runtime and to inspect backends, guarded by `admin_token`. Changes to
+/// users are made in memory only, so they are lost when the config file is reloaded or thor restarts.
+pub fn admin_router(state: SharedState, admin_token: &str) -> Router {
+    Router::new()
+        .route("/admin/users", get(list_users).post(add_user))
+        .route("/admin/users/{username}", delete(delete_user))
+        .route(
+            "/admin/users/{username}/last-invoice",
+            get(get_last_invoice),
+        )
+        .route("/status", get(get_status))
+        .route("/balance/{username}", get(get_balance))
+        .with_state(state)
+        .layer(middleware::from_fn_with_state(
+            Arc::<str>::from(admin_token),
+            require_admin_token,
+        ))
+}
+
+pub async fn run_admin_server(
+    listen_addr: &str,
+    app: Router,
+    shutdown: ShutdownListener,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen_addr).await?;
+    tracing::info!("admin listening on {}", listen_addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.wait())
+        .await?;
+    Ok(())
+}
+
+async fn require_admin_token(
+    State(admin_token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), admin_token.as_bytes()));
+    if !authorized {
+        let e = Lud06Error::new("invalid admin token".to_string());
+        return Err(HttpError::new(StatusCode::UNAUTHORIZED, e));
+    }
+    Ok(next.run(request).await)
+}
+
+// so that the token can't be guessed byte by byte from the response time.
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn list_users(State(state): State<SharedState>) -> Json<Vec<UserSummary>> {
+    let state = state.load_full();
+    let mut users: Vec<_> = state
+        .all_users()
+        .iter()
+        .map(|(username, user)| UserSummary::new(username, user))
+        .collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(users)
+}
+
+async fn add_user(
+    State(state): State<SharedState>,
+    Json(user_config): Json<UserConfig>,
+) -> Result<(StatusCode, Json<UserSummary>), HttpError> {
+    let state = state.load_full();
+    let bad_request =
+        |e: anyhow::Error| HttpError::new(StatusCode::BAD_REQUEST, Lud06Error::new(e.to_string()));
+    user_config
+        .validate(&state.domain)
+        .map_err(|e| bad_request(e.into()))?;
+    let user = User::new(
+        &user_config,
+        state.max_avatar_bytes,
+        state.invoice_creator_factory.as_ref(),
+    )
+    .map_err(bad_request)?;
+    let pubkey = user_config.nostr_pubkey_hex().map_err(bad_request)?;
+
+    // always locked in this order, users first.
+    let mut users = state.users.write().unwrap_or_else(|e| e.into_inner());
+    let mut nostr_pubkeys = state
+        .nostr_pubkeys
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    let names: Vec<_> = std::iter::once(&user_config.name)
+        .chain(&user_config.aliases)
+        .map(|name| name.to_lowercase())
+        .collect();
+    if let Some(taken) = names.iter().find(|name| users.contains_key(*name)) {
+        let e = Lud06Error::new(format!("user {} already exists", taken));
+        return Err(HttpError::new(StatusCode::CONFLICT, e));
+    }
+    if let Some(pubkey) = &pubkey
+        && nostr_pubkeys.contains_key(pubkey)
+    {
+        let e = Lud06Error::new(format!("nostr pubkey {} is already taken", pubkey));
+        return Err(HttpError::new(StatusCode::CONFLICT, e));
+    }
+    let summary = UserSummary::new(&user.name, &user);
+    let user = Arc::new(user);
+    for name in names {
+        users.insert(name, user.clone());
+    }
+    if let Some(pubkey) = pubkey {
+        nostr_pubkeys.insert(pubkey, user);
+    }
+    tracing::info!(user = user_config.name, "user added by admin.");
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+async fn delete_user(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, HttpError> {
+    let state = state.load_full();
+    let mut users = state.users.write().unwrap_or_else(|e| e.into_inner());
+    // whether by its name or an alias, the user is removed under all of them.
+    let removed = users.get(&username.to_lowercase()).cloned();
+    if let Some(removed) = &removed {
+        users.retain(|_, user| !Arc::ptr_eq(user, removed));
+        state
+            .nostr_pubkeys
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, user| !Arc::ptr_eq(user, removed));
+        state.last_invoices.remove(&removed.name);
+    }
+    match removed {
+        Some(_) => {
+            tracing::info!(user = username, "user deleted by admin.");
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => {
+            let e = Lud06Error::user_not_found(&username);
+            Err(HttpError::new(StatusCode::NOT_FOUND, e))
+        }
+    }
+}
+
+/// What the admin API tells about a user. Secrets like NWC URIs and runes are left out.
+#[derive(Debug, Serialize, Deserialize)]
+struct UserSummary {
+    name: String,
+    backends: Vec<String>,
+    min_sendable_msat: u64,
+    max_sendable_msat: u64,
+    comment_allowed: u64,
+}
+
+impl UserSummary {
+    fn new(username: &str, user: &User) -> UserSummary {
+        UserSummary {
+            name: username.to_string(),
+            backends: user
+                .invoice_creators
+                .iter()
+                .map(|creator| creator.backend().to_string())
+                .collect(),
+            min_sendable_msat: user.min_sendable,
+            max_sendable_msat: user.max_sendable,
+            comment_allowed: user.comment_allowed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::app_state;
+    use crate::webhook::InvoiceCreated;
+    use arc_swap::ArcSwap;
+    use axum::body::Body;
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "s3cret";
+
+    fn create_app() -> (SharedState, Router) {
+        let state: SharedState = Arc::new(ArcSwap::from_pointee(app_state(HashMap::new())));
+        let app = admin_router(state.clone(), TOKEN);
+        (state, app)
+    }
+
+    fn request(method: &str, uri: &str, token: Option<&str>, body: Option<&str>) -> Request {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    }
+
+    const ALICE: &str =
+        r#"{"name": "alice", "cln_rest": {"url": "https://127.0.0.1:3010", "rune": "rune"}}"#;
+
+    #[tokio::test]
+    async fn rejects_missing_or_wrong_token() {
+        let (_, app) = create_app();
+        for token in [None, Some("wrong")] {
+            let res = app
+                .clone()
+                .oneshot(request("GET", "/admin/users", token, None))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_lists_and_deletes_users() {
+        let (state, app) = create_app();
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/admin/users", Some(TOKEN), Some(ALICE)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(state.load().user("alice").is_some());
+
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/admin/users", Some(TOKEN), Some(ALICE)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let res = app
+            .clone()
+            .oneshot(request("GET", "/admin/users", Some(TOKEN), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let users: Vec<UserSummary> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "alice");
+        assert_eq!(users[0].backends, vec!["cln_rest"]);
+
+        let res = app
+            .clone()
+            .oneshot(request("DELETE", "/admin/users/alice", Some(TOKEN), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(state.load().user("alice").is_none());
+
+        let res = app
+            .oneshot(request("DELETE", "/admin/users/alice", Some(TOKEN), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn reports_last_invoice() {
+        let (state, app) = create_app();
+        let alice = ALICE.replace(
+            r#""name": "alice","#,
+            r#""name": "alice", "aliases": ["a"],"#,
+        );
+        app.clone()
+            .oneshot(request("POST", "/admin/users", Some(TOKEN), Some(&alice)))
+            .await
+            .unwrap();
+        let last_invoice = |username: &str| {
+            let uri = format!("/admin/users/{}/last-invoice", username);
+            app.clone().oneshot(request("GET", &uri, Some(TOKEN), None))
+        };
+        assert_eq!(
+            last_invoice("alice").await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        state
+            .load()
+            .last_invoices
+            .record(InvoiceCreated::now("alice", 1500, "lnbc1test"));
+        let res = last_invoice("a").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let invoice: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(invoice["bolt11"], "lnbc1test");
+        assert_eq!(invoice["amount_msat"], 1500);
+        assert!(invoice["created_at"].as_u64().unwrap() > 0);
+
+        assert_eq!(
+            last_invoice("bob").await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn adds_and_deletes_users_with_aliases() {
+        let (state, app) = create_app();
+        let alice = ALICE.replace(
+            r#""name": "alice","#,
+            r#""name": "alice", "aliases": ["a"],"#,
+        );
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/admin/users", Some(TOKEN), Some(&alice)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(state.load().user("a").unwrap().name, "alice");
+        assert_eq!(state.load().all_users().len(), 1);
+
+        // the alias is taken too.
+        let bob = ALICE.replace(r#""name": "alice","#, r#""name": "bob", "aliases": ["a"],"#);
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/admin/users", Some(TOKEN), Some(&bob)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert!(state.load().user("bob").is_none());
+
+        let res = app
+            .oneshot(request("DELETE", "/admin/users/a", Some(TOKEN), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(state.load().user("alice").is_none());
+        assert!(state.load().user("a").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_user_config() {
+        let (state, app) = create_app();
+        let res = app
+            .oneshot(request(
+                "POST",
+                "/admin/users",
+                Some(TOKEN),
+                Some(r#"{"name": "bob"}"#),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(state.load().user("bob").is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use super::SharedState;
+use crate::error::{HttpError, Lud06Error};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+/// Asks every backend of the user for its wallet balance. Backends that can't tell, or don't
+/// answer in `health_check_timeout_ms`, are reported with an error.
+pub async fn get_balance(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+) -> Result<Json<Vec<BackendBalance>>, HttpError> {
+    let state = state.load_full();
+    // disabled users are still reported, their wallets are still there.
+    let Some(user) = state.any_user(&username) else {
+        let e = Lud06Error::user_not_found(&username);
+        return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+    };
+
+    let balances = user
+        .invoice_creators
+        .iter()
+        .enumerate()
+        .map(|(backend_index, creator)| {
+            let state = &state;
+            async move {
+                let res =
+                    tokio::time::timeout(state.health_check_timeout, creator.get_balance()).await;
+                let (balance_msat, error) = match res {
+                    Ok(Ok(balance_msat)) => (Some(balance_msat), None),
+                    Ok(Err(e)) => (None, Some(e.to_string())),
+                    Err(_) => (None, Some("timed out".to_string())),
+                };
+                BackendBalance {
+                    backend_index,
+                    backend: creator.backend().to_string(),
+                    balance_msat,
+                    error,
+                }
+            }
+        });
+    Ok(Json(join_all(balances).await))
+}
+
+/// `backend_index` is the position of the backend among the user's, primary ones first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackendBalance {
+    backend_index: usize,
+    backend: String,
+    balance_msat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_server::User;
+    use crate::invoice_creator::InvoiceCreator;
+    use crate::test_utils::app_state;
+    use anyhow::Result;
+    use arc_swap::ArcSwap;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    struct Wallet {
+        balance_msat: Option<u64>,
+    }
+
+    #[async_trait::async_trait]
+    impl InvoiceCreator for Wallet {
+        fn backend(&self) -> &'static str {
+            "nwc"
+        }
+
+        async fn check_health(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn create_invoice(
+            &self,
+            _amount_msat: u64,
+            _description: &str,
+            _comment: Option<&str>,
+            _expiry: Option<u64>,
+        ) -> Result<String> {
+            anyhow::bail!("not used")
+        }
+
+        async fn get_balance(&self) -> Result<u64> {
+            self.balance_msat
+                .ok_or_else(|| anyhow::anyhow!("wallet unreachable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_balance_of_each_backend() {
+        let user_config = serde_json::from_value(serde_json::json!({
+            "name": "alice",
+            "nwcs": ["nwc://example"],
+        }))
+        .unwrap();
+        let user = User::with_invoice_creators(
+            &user_config,
+            0,
+            vec![Arc::new(Wallet {
+                balance_msat: Some(21_000),
+            })],
+            vec![Arc::new(Wallet { balance_msat: None })],
+        )
+        .unwrap();
+        let state: SharedState = Arc::new(ArcSwap::from_pointee(app_state(HashMap::from([(
+            "alice".to_string(),
+            Arc::new(user),
+        )]))));
+
+        let Json(balances) = get_balance(State(state.clone()), Path("alice".to_string()))
+            .await
+            .unwrap();
+        let balances = serde_json::to_value(balances).unwrap();
+        assert_eq!(
+            balances,
+            serde_json::json!([
+                {"backend_index": 0, "backend": "nwc", "balance_msat": 21_000},
+                {"backend_index": 1, "backend": "nwc", "balance_msat": null, "error": "wallet unreachable"},
+            ])
+        );
+
+        let err = get_balance(State(state), Path("bob".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            axum::response::IntoResponse::into_response(err).status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use moka::sync::Cache;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// entries expire on their own, this only bounds the memory of a flood of distinct amounts or clients.
+const MAX_ENTRIES: u64 = 10_000;
+
+/// The last invoice of each user, amount and client IP, handed out again to the requests of the
+/// same client for the same amount within the user's `invoice_dedup_ttl_seconds`, rather than
+/// creating a new one. It spares the backend from clients polling the callback, without giving an
+/// invoice another payer may have paid already.
+pub struct RecentInvoices {
+    invoices: Cache<(String, u64, IpAddr), RecentInvoice>,
+}
+
+#[derive(Clone)]
+struct RecentInvoice {
+    bolt11: String,
+    expires_at: Instant,
+}
+
+impl RecentInvoices {
+    pub fn get(&self, username: &str, amount_msat: u64, client_ip: IpAddr) -> Option<String> {
+        self.invoices
+            .get(&(username.to_string(), amount_msat, client_ip))
+            .filter(|invoice| invoice.expires_at > Instant::now())
+            .map(|invoice| invoice.bolt11)
+    }
+
+    pub fn insert(
+        &self,
+        username: &str,
+        amount_msat: u64,
+        client_ip: IpAddr,
+        bolt11: &str,
+        ttl: Duration,
+    ) {
+        let invoice = RecentInvoice {
+            bolt11: bolt11.to_string(),
+            expires_at: Instant::now() + ttl,
+        };
+        self.invoices
+            .insert((username.to_string(), amount_msat, client_ip), invoice);
+    }
+}
+
+impl Default for RecentInvoices {
+    fn default() -> Self {
+        RecentInvoices {
+            invoices: Cache::new(MAX_ENTRIES),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_invoice_until_expired() {
+        let invoices = RecentInvoices::default();
+        let ip = "1.1.1.1".parse().unwrap();
+        invoices.insert("alice", 21_000, ip, "lnbc1", Duration::from_secs(60));
+        invoices.insert("bob", 21_000, ip, "lnbc2", Duration::ZERO);
+        assert_eq!(invoices.get("alice", 21_000, ip).as_deref(), Some("lnbc1"));
+        assert_eq!(invoices.get("alice", 42_000, ip), None);
+        assert_eq!(
+            invoices.get("alice", 21_000, "2.2.2.2".parse().unwrap()),
+            None
+        );
+        assert_eq!(invoices.get("bob", 21_000, ip), None);
+    }
+}
@@ -0,0 +1,165 @@
+use super::request_id::RequestId;
+use super::{
+    InvoiceParams, InvoiceResponse, LnUrlPayInfo, SharedState, callback_host, create_invoice,
+};
+use crate::error::{ErrorCode, HttpError, Lud06Error};
+use crate::rate_limit::ClientIp;
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Json;
+use moka::Expiry;
+use moka::sync::Cache;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// links expire on their own, this only bounds the memory of a flood of link requests.
+const MAX_LINKS: u64 = 100_000;
+
+/// LUD-11: pay links that can only be paid to once, by their token.
+pub struct DisposableLinks {
+    links: Cache<Uuid, DisposableLink>,
+}
+
+#[derive(Debug, Clone)]
+struct DisposableLink {
+    username: String,
+    expires_at: Instant,
+}
+
+/// Evicts each link once it expires, whatever the ttl was when it was issued.
+struct LinkExpiry;
+
+impl Expiry<Uuid, DisposableLink> for LinkExpiry {
+    fn expire_after_create(
+        &self,
+        _token: &Uuid,
+        link: &DisposableLink,
+        created_at: Instant,
+    ) -> Option<Duration> {
+        Some(link.expires_at.saturating_duration_since(created_at))
+    }
+}
+
+impl DisposableLinks {
+    fn issue(&self, username: &str, ttl: Duration) -> Uuid {
+        let token = Uuid::new_v4();
+        self.links.insert(
+            token,
+            DisposableLink {
+                username: username.to_string(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+        token
+    }
+
+    /// Removes the link, so that concurrent requests can't both use it.
+    fn take(&self, token: &Uuid) -> Option<DisposableLink> {
+        self.links
+            .remove(token)
+            .filter(|link| link.expires_at > Instant::now())
+    }
+
+    /// Gives back a link taken by a request that failed to create an invoice.
+    fn restore(&self, token: Uuid, link: DisposableLink) {
+        self.links.insert(token, link);
+    }
+}
+
+impl Default for DisposableLinks {
+    fn default() -> Self {
+        DisposableLinks {
+            links: Cache::builder()
+                .max_capacity(MAX_LINKS)
+                .expire_after(LinkExpiry)
+                .build(),
+        }
+    }
+}
+
+pub async fn get_disposable_lnurlp_info(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<LnUrlPayInfo>, HttpError> {
+    let state = state.load_full();
+    let user = match state.user(&username) {
+        Some(user) => user,
+        None => {
+            let e = Lud06Error::user_not_found(&username);
+            return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+        }
+    };
+
+    let token = state
+        .disposable_links
+        .issue(&user.name, state.disposable_link_ttl);
+    let callback = format!(
+        "https://{}/lnurlp/once/{}",
+        callback_host(&state, &headers),
+        token
+    );
+    let mut metadata = LnUrlPayInfo::new(&state, &user.name, &user, callback).await?;
+    metadata.disposable = Some(true);
+    Ok(Json(metadata))
+}
+
+pub async fn create_disposable_invoice(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    params: Query<InvoiceParams>,
+    request_id: Option<Extension<RequestId>>,
+    client_ip: Option<Extension<ClientIp>>,
+) -> Result<Json<InvoiceResponse>, HttpError> {
+    let disposable_links = state.load().disposable_links.clone();
+    let taken = Uuid::parse_str(&token)
+        .ok()
+        .and_then(|token| Some((token, disposable_links.take(&token)?)));
+    let Some((token, link)) = taken else {
+        let e = Lud06Error::new("link expired or already used".to_string())
+            .with_code(ErrorCode::LinkExpired);
+        return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+    };
+
+    let res = create_invoice(
+        State(state),
+        Path(link.username.clone()),
+        params,
+        request_id,
+        client_ip,
+    )
+    .await;
+    if res.is_err() {
+        disposable_links.restore(token, link);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_can_only_be_taken_once() {
+        let links = DisposableLinks::default();
+        let token = links.issue("alice", Duration::from_secs(60));
+        let link = links.take(&token).unwrap();
+        assert_eq!(link.username, "alice");
+        assert!(links.take(&token).is_none());
+
+        links.restore(token, link);
+        assert!(links.take(&token).is_some());
+    }
+
+    #[test]
+    fn expired_links_are_rejected_and_dropped() {
+        let links = DisposableLinks::default();
+        let expired = links.issue("alice", Duration::ZERO);
+        assert!(links.take(&expired).is_none());
+
+        let expired = links.issue("alice", Duration::ZERO);
+        let live = links.issue("bob", Duration::from_secs(60));
+        assert!(!links.links.contains_key(&expired));
+        assert!(links.links.contains_key(&live));
+    }
+}
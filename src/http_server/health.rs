@@ -0,0 +1,139 @@
+use super::{AppState, SharedState};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Json;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long the result of probing the backends is answered to `/health` again.
+const HEALTH_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// The last result of `check_health`, so that polling `/health`, which anyone can, doesn't hit
+/// the backends on every request. Concurrent requests wait for the same probe.
+#[derive(Default)]
+pub struct HealthCache {
+    last: Mutex<Option<(Instant, HealthResponse)>>,
+}
+
+/// Probes every backend of every enabled user, at most once per `HEALTH_CACHE_TTL`. Returns 200
+/// only if each of them has at least one reachable backend.
+pub async fn get_health(State(state): State<SharedState>) -> (StatusCode, Json<HealthResponse>) {
+    let state = state.load_full();
+    let res = {
+        let mut last = state.health_cache.last.lock().await;
+        match last.as_ref() {
+            Some((checked_at, res)) if checked_at.elapsed() < HEALTH_CACHE_TTL => res.clone(),
+            _ => {
+                let res = check_health(&state).await;
+                *last = Some((Instant::now(), res.clone()));
+                res
+            }
+        }
+    };
+    let status_code = if res.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status_code, Json(res))
+}
+
+async fn check_health(state: &AppState) -> HealthResponse {
+    let users = state.enabled_users();
+    let checks = users.iter().map(|(username, user)| async move {
+        let backends = join_all(user.invoice_creators.iter().map(|creator| async move {
+            let res =
+                tokio::time::timeout(state.health_check_timeout, creator.check_health()).await;
+            let error = match res {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some("timed out".to_string()),
+            };
+            if let Some(error) = &error {
+                tracing::warn!(
+                    user = username,
+                    backend = creator.backend(),
+                    error = error,
+                    "health check failed."
+                );
+            }
+            BackendHealth {
+                backend: creator.backend().to_string(),
+                ok: error.is_none(),
+                error,
+            }
+        }))
+        .await;
+        (username.clone(), backends)
+    });
+    let users: BTreeMap<_, _> = join_all(checks).await.into_iter().collect();
+    let healthy = users
+        .values()
+        .all(|backends| backends.iter().any(|backend| backend.ok));
+    HealthResponse { healthy, users }
+}
+
+/// How long each backend gets to answer on startup.
+const WARM_UP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Probes every backend of every enabled user on startup, so that a broken backend, e.g. a
+/// mistyped NWC URI, shows up in the logs before the first payer does. Returns the failures, which
+/// are also logged.
+pub async fn warm_up(states: &[Arc<AppState>]) -> Vec<String> {
+    let checks = states.iter().flat_map(|state| {
+        state
+            .enabled_users()
+            .into_iter()
+            .flat_map(move |(username, user)| {
+                (0..user.invoice_creators.len()).map(move |i| {
+                    let (username, user) = (username.clone(), user.clone());
+                    async move {
+                        let creator = &user.invoice_creators[i];
+                        let error =
+                            match tokio::time::timeout(WARM_UP_TIMEOUT, creator.check_health())
+                                .await
+                            {
+                                Ok(Ok(())) => return None,
+                                Ok(Err(e)) => e.to_string(),
+                                Err(_) => "timed out".to_string(),
+                            };
+                        tracing::warn!(
+                            domain = state.domain(),
+                            user = username,
+                            backend = creator.backend(),
+                            endpoint = creator.endpoint(),
+                            error = error,
+                            "backend warm-up failed."
+                        );
+                        Some(format!(
+                            "{}@{}: {} {}: {}",
+                            username,
+                            state.domain(),
+                            creator.backend(),
+                            creator.endpoint(),
+                            error
+                        ))
+                    }
+                })
+            })
+    });
+    join_all(checks).await.into_iter().flatten().collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthResponse {
+    healthy: bool,
+    users: BTreeMap<String, Vec<BackendHealth>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackendHealth {
+    backend: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
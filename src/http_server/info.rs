@@ -0,0 +1,24 @@
+use super::SharedState;
+use axum::extract::State;
+use axum::response::Json;
+use serde::{Deserialize, Serialize};
+
+/// What's running, for monitoring. It's public, so it tells nothing about the users but their
+/// number.
+pub async fn get_info(State(state): State<SharedState>) -> Json<ServerInfo> {
+    let state = state.load_full();
+    Json(ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        domain: state.domain.clone(),
+        user_count: state.enabled_users().len(),
+        uptime_seconds: state.started_at.elapsed().as_secs(),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    version: String,
+    domain: String,
+    user_count: usize,
+    uptime_seconds: u64,
+}
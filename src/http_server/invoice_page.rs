@@ -0,0 +1,107 @@
+use super::lnurl::qr_png;
+use super::request_id::RequestId;
+use super::{InvoiceParams, SharedState, create_invoice};
+use crate::error::HttpError;
+use crate::rate_limit::ClientIp;
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::{HeaderMap, header};
+use axum::response::{Html, IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+/// For payers without a Lightning wallet which speaks LNURL, an invoice on a web page, as text
+/// and as a QR code. It takes the same parameters as the pay callback, and returns its JSON
+/// instead to clients asking for `application/json`.
+/// The page reloads itself every 30 seconds, with a new invoice.
+pub async fn get_invoice_page(
+    state: State<SharedState>,
+    username: Path<String>,
+    params: Query<InvoiceParams>,
+    request_id: Option<Extension<RequestId>>,
+    client_ip: Option<Extension<ClientIp>>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    let invoice = create_invoice(state, username, params, request_id, client_ip).await?;
+    if accepts_json(&headers) {
+        return Ok(invoice.into_response());
+    }
+    // it's put in the page as is, which only plain bech32 is safe for.
+    if !is_bech32(&invoice.pr) {
+        return Err(anyhow::anyhow!("backend returned an invalid invoice").into());
+    }
+    // uppercase fits the QR alphanumeric mode, which makes a smaller code.
+    let png = qr_png(&invoice.pr.to_uppercase())?;
+    let page = invoice_page(&invoice.pr, &BASE64.encode(png));
+    // set here, the security headers middleware leaves it alone.
+    Ok(([(header::CONTENT_SECURITY_POLICY, PAGE_CSP)], Html(page)).into_response())
+}
+
+/// The default policy, except for the inline QR code.
+const PAGE_CSP: &str = "default-src 'none'; img-src data:; frame-ancestors 'none'";
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            let media_type = media_type.split(';').next().unwrap_or_default().trim();
+            media_type.eq_ignore_ascii_case("application/json")
+        })
+}
+
+/// Lowercase bech32, as bolt11 invoices are, which needs no escaping in HTML.
+fn is_bech32(bolt11: &str) -> bool {
+    !bolt11.is_empty()
+        && bolt11
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+}
+
+// `bolt11` must be checked by `is_bech32` first.
+fn invoice_page(bolt11: &str, png_base64: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="30">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Lightning invoice</title>
+</head>
+<body>
+<p><a href="lightning:{bolt11}"><img src="data:image/png;base64,{png_base64}" alt="QR code of the invoice"></a></p>
+<p><textarea readonly rows="8" cols="40">{bolt11}</textarea></p>
+</body>
+</html>
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn negotiates_json() {
+        let accept = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+            accepts_json(&headers)
+        };
+        assert!(accept("application/json"));
+        assert!(accept("text/plain, Application/JSON; q=0.9"));
+        assert!(!accept("text/html,application/xhtml+xml,*/*;q=0.8"));
+        assert!(!accepts_json(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn only_bech32_is_rendered() {
+        assert!(is_bech32("lnbc15n1pj9x7abc"));
+        assert!(!is_bech32("LNBC15N1PJ9X7ABC"));
+        assert!(!is_bech32("lnbc1\"><script>alert(1)</script>"));
+        assert!(!is_bech32(""));
+    }
+}
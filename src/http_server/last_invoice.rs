@@ -0,0 +1,76 @@
+use super::SharedState;
+use crate::error::{HttpError, Lud06Error};
+use crate::webhook::InvoiceCreated;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// The last invoice created for each user, for smoke-testing a deployment.
+#[derive(Default)]
+pub struct LastInvoices {
+    invoices: RwLock<HashMap<String, InvoiceCreated>>,
+}
+
+impl LastInvoices {
+    pub fn record(&self, invoice: InvoiceCreated) {
+        self.invoices
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(invoice.username.clone(), invoice);
+    }
+
+    fn get(&self, username: &str) -> Option<InvoiceCreated> {
+        self.invoices
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(username)
+            .cloned()
+    }
+
+    pub fn remove(&self, username: &str) {
+        self.invoices
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(username);
+    }
+}
+
+pub async fn get_last_invoice(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+) -> Result<Json<InvoiceCreated>, HttpError> {
+    let state = state.load_full();
+    let not_found = |e: Lud06Error| HttpError::new(StatusCode::NOT_FOUND, e);
+    let user = state
+        .any_user(&username)
+        .ok_or_else(|| not_found(Lud06Error::user_not_found(&username)))?;
+    let invoice = state.last_invoices.get(&user.name).ok_or_else(|| {
+        not_found(Lud06Error::new(format!(
+            "no invoice created for user {} yet",
+            user.name
+        )))
+    })?;
+    Ok(Json(invoice))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_invoice_of_each_user() {
+        let last_invoices = LastInvoices::default();
+        last_invoices.record(InvoiceCreated::now("alice", 1000, "lnbc1first"));
+        last_invoices.record(InvoiceCreated::now("alice", 2000, "lnbc1second"));
+        last_invoices.record(InvoiceCreated::now("bob", 3000, "lnbc1bob"));
+        let alice = last_invoices.get("alice").unwrap();
+        assert_eq!(alice.bolt11, "lnbc1second");
+        assert_eq!(alice.amount_msat, 2000);
+
+        last_invoices.remove("alice");
+        assert!(last_invoices.get("alice").is_none());
+        assert_eq!(last_invoices.get("bob").unwrap().bolt11, "lnbc1bob");
+    }
+}
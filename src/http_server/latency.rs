@@ -0,0 +1,52 @@
+use crate::metrics::BACKEND_LATENCY;
+use hdrhistogram::Histogram;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// exported as the `quantile` label, as Prometheus summaries do.
+const QUANTILES: [(f64, &str); 3] = [(0.5, "0.5"), (0.95, "0.95"), (0.99, "0.99")];
+
+/// Latencies of the invoice creation attempts in ms, keyed by `InvoiceCreator::endpoint`, and
+/// exported as percentiles on `/metrics`. They start over when the config is reloaded.
+#[derive(Default)]
+pub struct BackendLatencies {
+    histograms: Mutex<HashMap<String, Histogram<u64>>>,
+}
+
+impl BackendLatencies {
+    pub fn record(&self, backend: &str, endpoint: &str, latency: Duration) {
+        let mut histograms = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        let histogram = histograms
+            .entry(endpoint.to_string())
+            .or_insert_with(|| Histogram::new(3).expect("3 significant figures are supported"));
+        histogram.saturating_record(latency.as_millis() as u64);
+        // the percentiles only change here, so they are exported right away.
+        for (quantile, label) in QUANTILES {
+            BACKEND_LATENCY
+                .with_label_values(&[backend, endpoint, label])
+                .set(histogram.value_at_quantile(quantile) as f64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_percentiles() {
+        let latencies = BackendLatencies::default();
+        for ms in 1..=100 {
+            latencies.record("nwc", "nwc://latency-test", Duration::from_millis(ms));
+        }
+        let percentile = |quantile| {
+            BACKEND_LATENCY
+                .with_label_values(&["nwc", "nwc://latency-test", quantile])
+                .get()
+        };
+        assert_eq!(percentile("0.5"), 50.0);
+        assert_eq!(percentile("0.95"), 95.0);
+        assert_eq!(percentile("0.99"), 99.0);
+    }
+}
@@ -0,0 +1,125 @@
+use super::{AppState, SharedState, callback_host};
+use crate::error::{HttpError, Lud06Error};
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
+use bech32::{Bech32, Hrp};
+use image::{DynamicImage, ImageFormat, Luma};
+use qrcode::QrCode;
+use serde::Serialize;
+use std::io::Cursor;
+
+// - [LUD-01: Base LNURL encoding and decoding](https://github.com/lnurl/luds/blob/luds/01.md)
+/// For wallets which don't support lightning addresses, the pay endpoint encoded as `lnurl1...`.
+pub async fn get_lnurl(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<LnUrl>, HttpError> {
+    let state = state.load_full();
+    let lnurl = user_lnurl(&state, &username, &headers)?;
+    Ok(Json(LnUrl { lnurl }))
+}
+
+/// The LNURL of the pay endpoint as a QR code, to be shared from a browser.
+pub async fn get_lnurl_qr(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    let state = state.load_full();
+    let lnurl = user_lnurl(&state, &username, &headers)?;
+    // LUD-01: uppercase fits the QR alphanumeric mode, which makes a smaller code.
+    let png = qr_png(&lnurl.to_uppercase())?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// The lightning address itself as a QR code, `user@domain`, for wallets scanning it from a page.
+pub async fn get_address_qr(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    let state = state.load_full();
+    if state.user(&username).is_none() {
+        let e = Lud06Error::user_not_found(&username);
+        return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+    }
+    let address = format!("{}@{}", username, callback_host(&state, &headers));
+    let png = qr_png(&address)?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// The usernames of the domain, for LNURL explorers, if the operator opted in with `list_users`.
+/// Disabled users and aliases are left out.
+pub async fn get_usernames(State(state): State<SharedState>) -> Result<Json<Usernames>, HttpError> {
+    let state = state.load_full();
+    if !state.list_users {
+        let e = Lud06Error::new("listing users is disabled".to_string());
+        return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+    }
+    let mut users: Vec<String> = state
+        .enabled_users()
+        .into_iter()
+        .map(|(username, _)| username)
+        .collect();
+    users.sort();
+    Ok(Json(Usernames { users }))
+}
+
+fn user_lnurl(state: &AppState, username: &str, headers: &HeaderMap) -> Result<String, HttpError> {
+    if state.user(username).is_none() {
+        let e = Lud06Error::user_not_found(username);
+        return Err(HttpError::new(StatusCode::NOT_FOUND, e));
+    }
+    let url = format!(
+        "https://{}/.well-known/lnurlp/{}",
+        callback_host(state, headers),
+        username
+    );
+    Ok(encode_lnurl(&url)?)
+}
+
+fn encode_lnurl(url: &str) -> Result<String> {
+    let hrp = Hrp::parse("lnurl")?;
+    Ok(bech32::encode::<Bech32>(hrp, url.as_bytes())?)
+}
+
+pub fn qr_png(data: &str) -> Result<Vec<u8>> {
+    let image = QrCode::new(data)?.render::<Luma<u8>>().build();
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usernames {
+    users: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LnUrl {
+    lnurl: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_lnurl() {
+        // the example of LUD-01.
+        let url = "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df";
+        assert_eq!(
+            encode_lnurl(url).unwrap(),
+            "lnurl1dp68gurn8ghj7um9wfmxjcm99e3k7mf0v9cxj0m385ekvcenxc6r2c35xvukxefcv5mkvv34x5ekzd3ev56nyd3hxqurzepexejxxepnxscrvwfnv9nxzcn9xq6xyefhvgcxxcmyxymnserxfq5fns"
+        );
+    }
+
+    #[test]
+    fn renders_png() {
+        let png = qr_png("LNURL1DP68GURN8GHJ7").unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
@@ -0,0 +1,124 @@
+use super::SharedState;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Msat invoiced per user since midnight UTC.
+#[derive(Default)]
+pub struct DailyTotals {
+    totals: Mutex<HashMap<String, Arc<AtomicU64>>>,
+}
+
+impl DailyTotals {
+    fn total(&self, username: &str) -> Arc<AtomicU64> {
+        self.totals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(username.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Adds `amount_msat` to the total of the user, unless that would exceed `cap_msat`.
+    /// The amount is given back when the reservation is dropped without being committed.
+    pub fn try_reserve(
+        &self,
+        username: &str,
+        amount_msat: u64,
+        cap_msat: u64,
+    ) -> Option<Reservation> {
+        let total = self.total(username);
+        total
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                total
+                    .checked_add(amount_msat)
+                    .filter(|&total| total <= cap_msat)
+            })
+            .ok()?;
+        Some(Reservation {
+            total,
+            amount_msat,
+            committed: false,
+        })
+    }
+
+    fn reset(&self) {
+        for total in self
+            .totals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+        {
+            total.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// An amount counted in a daily total for an invoice being created. Whenever the invoice isn't
+/// created after all, because it failed, the request timed out or the client went away, the
+/// reservation is dropped and the amount given back.
+pub struct Reservation {
+    total: Arc<AtomicU64>,
+    amount_msat: u64,
+    committed: bool,
+}
+
+impl Reservation {
+    /// Keeps the amount counted, once the invoice is created.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self
+                .total
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+                    Some(total.saturating_sub(self.amount_msat))
+                });
+        }
+    }
+}
+
+/// Resets the daily totals of invoices and withdrawals at every midnight UTC.
+pub async fn reset_daily_totals(state: SharedState) {
+    let since_midnight = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % DAY.as_secs();
+    let until_midnight = DAY - Duration::from_secs(since_midnight);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + until_midnight, DAY);
+    loop {
+        interval.tick().await;
+        let state = state.load();
+        state.daily_totals.reset();
+        state.withdrawn_totals.reset();
+        tracing::info!("daily invoice and withdraw totals reset.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve_up_to_cap() {
+        let totals = DailyTotals::default();
+        let reservation = totals.try_reserve("alice", 600, 1_000).unwrap();
+        assert!(totals.try_reserve("alice", 600, 1_000).is_none());
+        totals.try_reserve("bob", 600, 1_000).unwrap().commit();
+
+        drop(reservation);
+        totals.try_reserve("alice", 1_000, 1_000).unwrap().commit();
+        assert!(totals.try_reserve("alice", 1, 1_000).is_none());
+
+        totals.reset();
+        assert!(totals.try_reserve("alice", 1_000, 1_000).is_some());
+    }
+}
@@ -0,0 +1,107 @@
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+use uuid::Uuid;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// long enough for the ids of common proxies, short enough to keep out of the way in logs.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The id of the request being handled, put in the request extensions by [`request_id`].
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Tags every request with an id, for correlating support requests with log entries.
+/// The id is taken from the `X-Request-ID` request header if there is a valid one, or a new
+/// UUID otherwise. It's recorded on a span wrapping the request, and returned in the
+/// `X-Request-ID` response header.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let incoming = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string);
+    let reused = incoming.is_some();
+    let request_id = incoming.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = tracing::info_span!("request", request_id);
+    if reused {
+        span.in_scope(|| tracing::info!("reusing the request id from the X-Request-ID header."));
+    }
+
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+    let mut res = next.run(req).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+    res
+}
+
+// the id ends up in logs and response headers, so only a conservative charset is accepted.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{Extension, Router, middleware};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(RequestId(id)): Extension<RequestId>| async move { id }),
+            )
+            .layer(middleware::from_fn(request_id))
+    }
+
+    async fn send(incoming: Option<&str>) -> (String, String) {
+        let mut builder = Request::builder().uri("/");
+        if let Some(incoming) = incoming {
+            builder = builder.header(&X_REQUEST_ID, incoming);
+        }
+        let res = app()
+            .oneshot(builder.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let header = res.headers()[&X_REQUEST_ID].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (header, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn generates_request_id() {
+        let (header, seen_by_handler) = send(None).await;
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(header, seen_by_handler);
+        assert_ne!(send(None).await.0, header);
+    }
+
+    #[tokio::test]
+    async fn reuses_incoming_request_id() {
+        let (header, seen_by_handler) = send(Some("abc-123")).await;
+        assert_eq!(header, "abc-123");
+        assert_eq!(seen_by_handler, "abc-123");
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_request_id() {
+        for incoming in ["", "a b", &"a".repeat(MAX_REQUEST_ID_LEN + 1)] {
+            let (header, _) = send(Some(incoming)).await;
+            assert!(Uuid::parse_str(&header).is_ok(), "{:?}", incoming);
+        }
+    }
+}
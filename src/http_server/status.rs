@@ -0,0 +1,70 @@
+use super::SharedState;
+use axum::extract::State;
+use axum::response::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stats of every backend, keyed by `InvoiceCreator::endpoint`. They start over when the config is reloaded.
+pub type BackendStatsMap = Arc<Mutex<BTreeMap<String, BackendStats>>>;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendStats {
+    pub backend: String,
+    pub invoices_created: u64,
+    pub invoices_failed: u64,
+    /// Unix timestamp in seconds.
+    pub last_error_at: Option<u64>,
+}
+
+/// Counts the outcome of a single invoice creation attempt.
+pub fn record(stats: &BackendStatsMap, backend: &str, endpoint: String, ok: bool) {
+    let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = stats.entry(endpoint).or_insert_with(|| BackendStats {
+        backend: backend.to_string(),
+        ..Default::default()
+    });
+    if ok {
+        entry.invoices_created += 1;
+    } else {
+        entry.invoices_failed += 1;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        entry.last_error_at = Some(now);
+    }
+}
+
+pub async fn get_status(State(state): State<SharedState>) -> Json<BTreeMap<String, BackendStats>> {
+    let state = state.load_full();
+    let stats = state
+        .backend_stats
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Json(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_counts_attempts() {
+        let stats = BackendStatsMap::default();
+        record(&stats, "nwc", "nwc://a".to_string(), true);
+        record(&stats, "nwc", "nwc://a".to_string(), false);
+        record(&stats, "nwc", "nwc://a".to_string(), true);
+        record(&stats, "cln_rest", "https://cln".to_string(), true);
+
+        let stats = stats.lock().unwrap();
+        let a = &stats["nwc://a"];
+        assert_eq!(a.backend, "nwc");
+        assert_eq!(a.invoices_created, 2);
+        assert_eq!(a.invoices_failed, 1);
+        assert!(a.last_error_at.is_some());
+        assert_eq!(stats["https://cln"].last_error_at, None);
+    }
+}
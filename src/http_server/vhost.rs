@@ -0,0 +1,75 @@
+use axum::Router;
+use axum::extract::Request;
+use axum::http::header;
+use axum::http::uri::Authority;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// Dispatches each request to the router of the domain it's made to, by its `Host` header.
+/// Requests to any other host go to `default`.
+pub fn virtual_hosts(default: Router, hosts: HashMap<String, Router>) -> Router {
+    let hosts: Arc<HashMap<String, Router>> = Arc::new(
+        hosts
+            .into_iter()
+            .map(|(domain, router)| (domain.to_ascii_lowercase(), router))
+            .collect(),
+    );
+    Router::new().fallback_service(tower::service_fn(move |request: Request| {
+        let router = request_host(&request)
+            .and_then(|host| hosts.get(&host))
+            .unwrap_or(&default)
+            .clone();
+        router.oneshot(request)
+    }))
+}
+
+// HTTP/2 requests carry the host in the uri instead of the header.
+fn request_host(request: &Request) -> Option<String> {
+    let host = match request.headers().get(header::HOST) {
+        Some(value) => value
+            .to_str()
+            .ok()?
+            .parse::<Authority>()
+            .ok()?
+            .host()
+            .to_string(),
+        None => request.uri().host()?.to_string(),
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+
+    async fn served_by(app: &Router, host: Option<&str>) -> String {
+        let mut builder = Request::builder().uri("/");
+        if let Some(host) = host {
+            builder = builder.header(header::HOST, host);
+        }
+        let res = app
+            .clone()
+            .oneshot(builder.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn dispatches_by_host() {
+        let default = Router::new().route("/", get(|| async { "default" }));
+        let other = Router::new().route("/", get(|| async { "other" }));
+        let app = virtual_hosts(default, HashMap::from([("Other.com".to_string(), other)]));
+
+        assert_eq!(served_by(&app, Some("other.com")).await, "other");
+        assert_eq!(served_by(&app, Some("OTHER.com:8080")).await, "other");
+        assert_eq!(served_by(&app, Some("example.com")).await, "default");
+        assert_eq!(served_by(&app, None).await, "default");
+    }
+}
@@ -0,0 +1,419 @@
+use super::admin::constant_time_eq;
+use super::{AppState, SharedState, User, callback_host};
+use crate::config::WithdrawConfig;
+use crate::error::{ErrorCode, HttpError, Lud06Error};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Json;
+use lightning_invoice::Bolt11Invoice;
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const SESSION_TTL: Duration = Duration::from_secs(300);
+// only issued to callers who know the secret of a withdraw link, this bounds them anyway.
+const MAX_SESSIONS: u64 = 10_000;
+
+/// The one-time `k1`s given to the callers of the withdraw links, by which they can withdraw once,
+/// within `SESSION_TTL`.
+pub struct WithdrawSessions {
+    sessions: Cache<String, String>, // k1 to username
+}
+
+impl WithdrawSessions {
+    fn issue(&self, username: &str) -> String {
+        let k1: String = rand::random::<[u8; 32]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.sessions.insert(k1.clone(), username.to_string());
+        k1
+    }
+
+    fn get(&self, k1: &str) -> Option<String> {
+        self.sessions.get(k1)
+    }
+
+    /// Removes the session, so that it can't be used again, even concurrently.
+    fn take(&self, k1: &str) -> Option<String> {
+        self.sessions.remove(k1)
+    }
+}
+
+impl Default for WithdrawSessions {
+    fn default() -> Self {
+        WithdrawSessions {
+            sessions: Cache::builder()
+                .max_capacity(MAX_SESSIONS)
+                .time_to_live(SESSION_TTL)
+                .build(),
+        }
+    }
+}
+
+// - [LUD-03: withdrawRequest base spec](https://github.com/lnurl/luds/blob/luds/03.md)
+/// Takes the secret `k1` of the withdraw link, and answers with a one-time `k1` for the callback.
+pub async fn get_lnurlw_info(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    Query(params): Query<WithdrawInfoParams>,
+    headers: HeaderMap,
+) -> Result<Json<LnUrlWithdrawInfo>, HttpError> {
+    let state = state.load_full();
+    let (user, withdraw) = authorize(&state, &username, &params.k1)?;
+
+    Ok(Json(LnUrlWithdrawInfo {
+        tag: "withdrawRequest",
+        callback: format!(
+            "https://{}/lnurlw/{}",
+            callback_host(&state, &headers),
+            username
+        ),
+        k1: state.withdraw_sessions.issue(&user.name),
+        default_description: format!("withdraw from {}@{}", username, state.domain),
+        min_withdrawable: withdraw.min_withdrawable_msat,
+        max_withdrawable: withdraw.max_withdrawable_msat,
+    }))
+}
+
+pub async fn withdraw(
+    State(state): State<SharedState>,
+    Path(username): Path<String>,
+    Query(params): Query<WithdrawParams>,
+) -> Result<Json<WithdrawResponse>, HttpError> {
+    let state = state.load_full();
+    let (user, withdraw) = find_session(&state, &username, &params.k1)?;
+
+    let bad_request =
+        |reason: String| HttpError::new(StatusCode::BAD_REQUEST, Lud06Error::new(reason));
+    let invoice = Bolt11Invoice::from_str(&params.pr)
+        .map_err(|e| bad_request(format!("invalid invoice: {}", e)))?;
+    // an invoice without amount would let the caller take whatever the wallet decides to pay.
+    let amount_msat = invoice
+        .amount_milli_satoshis()
+        .ok_or_else(|| bad_request("invoice must have an amount".to_string()))?;
+    if amount_msat < withdraw.min_withdrawable_msat || amount_msat > withdraw.max_withdrawable_msat
+    {
+        return Err(bad_request(format!(
+            "amount must be between {} and {} msat",
+            withdraw.min_withdrawable_msat, withdraw.max_withdrawable_msat
+        )));
+    }
+    // only once the invoice is accepted, so that a wallet sending a bad one can try again.
+    if state.withdraw_sessions.take(&params.k1).is_none() {
+        return Err(session_not_found(&username));
+    }
+
+    // paying is not idempotent, so unlike invoice creation it's never retried on another wallet.
+    let payer = user
+        .invoice_creators
+        .iter()
+        .find(|creator| creator.backend() == "nwc")
+        .ok_or_else(|| anyhow::anyhow!("user {} has no NWC wallet to pay from", username))?;
+    // counted even if paying fails, since the payment may still go through.
+    match state
+        .withdrawn_totals
+        .try_reserve(&user.name, amount_msat, withdraw.daily_cap_msat())
+    {
+        Some(reservation) => reservation.commit(),
+        None => {
+            tracing::warn!(user = username, "daily withdraw cap reached.");
+            let e =
+                Lud06Error::new("daily withdraw cap reached, please retry tomorrow".to_string())
+                    .with_code(ErrorCode::DailyCapReached);
+            return Err(HttpError::new(StatusCode::TOO_MANY_REQUESTS, e));
+        }
+    }
+    match payer.pay_invoice(&params.pr).await {
+        Ok(()) => {
+            tracing::info!(
+                user = username,
+                amount_msat = amount_msat,
+                invoice = params.pr,
+                "withdrawal paid."
+            );
+            Ok(Json(WithdrawResponse { status: "OK" }))
+        }
+        Err(e) => {
+            tracing::error!(user = username, error = %e, "failed to pay withdrawal.");
+            Err(e.into())
+        }
+    }
+}
+
+/// Only callers who know the `k1` of the user can see or use the withdraw link.
+fn authorize(
+    state: &AppState,
+    username: &str,
+    k1: &str,
+) -> Result<(Arc<User>, WithdrawConfig), HttpError> {
+    let not_found = || {
+        let e = Lud06Error::new(format!("withdraw not found for user {}", username));
+        HttpError::new(StatusCode::BAD_REQUEST, e)
+    };
+    let user = state.user(username).ok_or_else(not_found)?;
+    let withdraw = user.withdraw.clone().ok_or_else(not_found)?;
+    if !constant_time_eq(k1.as_bytes(), withdraw.k1.as_bytes()) {
+        return Err(not_found());
+    }
+    Ok((user, withdraw))
+}
+
+/// Only the one-time `k1` given by `get_lnurlw_info` for this user can be used. The session is
+/// left for `withdraw` to take once the invoice is accepted.
+fn find_session(
+    state: &AppState,
+    username: &str,
+    k1: &str,
+) -> Result<(Arc<User>, WithdrawConfig), HttpError> {
+    let not_found = || session_not_found(username);
+    let user = state.user(username).ok_or_else(not_found)?;
+    let withdraw = user.withdraw.clone().ok_or_else(not_found)?;
+    match state.withdraw_sessions.get(k1) {
+        Some(session_user) if session_user == user.name => Ok((user, withdraw)),
+        _ => Err(not_found()),
+    }
+}
+
+fn session_not_found(username: &str) -> HttpError {
+    let e = Lud06Error::new(format!(
+        "withdraw not found for user {}, or already used",
+        username
+    ));
+    HttpError::new(StatusCode::BAD_REQUEST, e)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WithdrawInfoParams {
+    k1: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WithdrawParams {
+    k1: String,
+    pr: String, // invoice to be paid
+}
+
+#[derive(Debug, Serialize)]
+pub struct LnUrlWithdrawInfo {
+    tag: &'static str, // "withdrawRequest"
+    callback: String,
+    k1: String,
+    #[serde(rename = "defaultDescription")]
+    default_description: String,
+    #[serde(rename = "minWithdrawable")]
+    min_withdrawable: u64, // msat
+    #[serde(rename = "maxWithdrawable")]
+    max_withdrawable: u64, // msat
+}
+
+#[derive(Debug, Serialize)]
+pub struct WithdrawResponse {
+    status: &'static str, // "OK"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConfig;
+    use crate::invoice_creator::{InvoiceCreator, InvoiceCreatorFactory, UserInvoiceCreators};
+    use crate::test_utils::app_state;
+    use anyhow::Result;
+    use arc_swap::ArcSwap;
+    use axum::response::IntoResponse;
+    use bitcoin::hashes::{Hash, sha256};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
+    use std::collections::HashMap;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Pays every invoice, as the NWC wallet of the user.
+    struct MockPayer;
+
+    #[async_trait::async_trait]
+    impl InvoiceCreator for MockPayer {
+        fn backend(&self) -> &'static str {
+            "nwc"
+        }
+
+        async fn check_health(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn create_invoice(
+            &self,
+            _amount_msat: u64,
+            _description: &str,
+            _comment: Option<&str>,
+            _expiry: Option<u64>,
+        ) -> Result<String> {
+            anyhow::bail!("only pays")
+        }
+
+        async fn pay_invoice(&self, _invoice: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct MockPayerFactory;
+
+    impl InvoiceCreatorFactory for MockPayerFactory {
+        fn create_from_config(&self, _user_config: &UserConfig) -> Result<UserInvoiceCreators> {
+            Ok(UserInvoiceCreators {
+                primary: vec![Arc::new(MockPayer)],
+                fallback: vec![],
+            })
+        }
+    }
+
+    fn create_state() -> SharedState {
+        let user_config: UserConfig = serde_json::from_value(serde_json::json!({
+            "name": "alice",
+            "nwcs": ["nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c"],
+            "withdraw": {"k1": "s3cret", "max_withdrawable_msat": 10_000},
+        }))
+        .unwrap();
+        let mut users = HashMap::new();
+        users.insert(
+            "alice".to_string(),
+            Arc::new(User::new(&user_config, 0, &MockPayerFactory).unwrap()),
+        );
+        Arc::new(ArcSwap::from_pointee(app_state(users)))
+    }
+
+    #[tokio::test]
+    async fn get_lnurlw_info_requires_k1() {
+        let state = create_state();
+        let res = get_lnurlw_info(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(WithdrawInfoParams {
+                k1: "wrong".to_string(),
+            }),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(
+            res.unwrap_err().into_response().status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let info = get_lnurlw_info(
+            State(state),
+            Path("alice".to_string()),
+            Query(WithdrawInfoParams {
+                k1: "s3cret".to_string(),
+            }),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.0.callback, "https://example.com/lnurlw/alice");
+        assert_eq!(info.0.min_withdrawable, 1_000);
+        assert_eq!(info.0.max_withdrawable, 10_000);
+    }
+
+    async fn session_k1(state: &SharedState) -> String {
+        let info = get_lnurlw_info(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(WithdrawInfoParams {
+                k1: "s3cret".to_string(),
+            }),
+            HeaderMap::new(),
+        )
+        .await
+        .unwrap();
+        info.0.k1
+    }
+
+    async fn withdraw_with(
+        state: &SharedState,
+        k1: &str,
+        pr: &str,
+    ) -> Result<Json<WithdrawResponse>, HttpError> {
+        withdraw(
+            State(state.clone()),
+            Path("alice".to_string()),
+            Query(WithdrawParams {
+                k1: k1.to_string(),
+                pr: pr.to_string(),
+            }),
+        )
+        .await
+    }
+
+    fn invoice(amount_msat: u64) -> String {
+        let node_key = SecretKey::from_slice(&[0x01; 32]).unwrap();
+        InvoiceBuilder::new(Currency::Bitcoin)
+            .description("withdraw".to_string())
+            .payment_hash(sha256::Hash::hash(b"preimage"))
+            .payment_secret(PaymentSecret([0x02; 32]))
+            .duration_since_epoch(SystemTime::now().duration_since(UNIX_EPOCH).unwrap())
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(amount_msat)
+            .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &node_key))
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn withdraw_rejects_invalid_invoice() {
+        let state = create_state();
+        let k1 = session_k1(&state).await;
+        let e = withdraw_with(&state, &k1, "lnbc1invalid")
+            .await
+            .unwrap_err();
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert!(
+            e.body().reason().starts_with("invalid invoice"),
+            "{}",
+            e.body().reason()
+        );
+        let e = withdraw_with(&state, &k1, &invoice(20_000))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.body().reason(),
+            "amount must be between 1000 and 10000 msat"
+        );
+
+        // the session is still there for a valid invoice.
+        assert!(withdraw_with(&state, &k1, &invoice(5_000)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn withdraw_k1_is_used_once() {
+        let state = create_state();
+        let k1 = session_k1(&state).await;
+        assert_ne!(k1, "s3cret");
+        assert_ne!(k1, session_k1(&state).await);
+
+        let pr = invoice(5_000);
+        assert!(withdraw_with(&state, &k1, &pr).await.is_ok());
+        let e = withdraw_with(&state, &k1, &pr).await.unwrap_err();
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            e.body().reason(),
+            "withdraw not found for user alice, or already used"
+        );
+        // the secret of the link only gives one-time k1s.
+        let e = withdraw_with(&state, "s3cret", &pr).await.unwrap_err();
+        assert_eq!(
+            e.body().reason(),
+            "withdraw not found for user alice, or already used"
+        );
+    }
+
+    #[test]
+    fn daily_cap_defaults_to_max_withdrawable() {
+        let withdraw: WithdrawConfig = serde_json::from_value(serde_json::json!({
+            "k1": "s3cret",
+            "max_withdrawable_msat": 10_000,
+        }))
+        .unwrap();
+        assert_eq!(withdraw.daily_cap_msat(), 10_000);
+    }
+}
@@ -1,29 +1,266 @@
-use super::InvoiceCreator;
+use super::{InvoiceCreator, InvoiceStatus};
 use anyhow::Result;
+use bitcoin_hashes::Sha256;
 use nwc::prelude::*;
+use std::ops::Deref;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
 #[async_trait::async_trait]
 impl InvoiceCreator for NwcInvoiceCreator {
-    async fn create_invoice(&self, amount_msat: u64, description_hash: &str) -> Result<String> {
-        let req = MakeInvoiceRequest {
-            amount: amount_msat,
-            description: None,
-            description_hash: Some(description_hash.to_string()),
-            expiry: None,
+    fn backend(&self) -> &'static str {
+        "nwc"
+    }
+
+    // the secret is left out, the wallet's pubkey and relays are enough to tell wallets apart.
+    fn endpoint(&self) -> String {
+        let relays: Vec<_> = self.pool.uri.relays.iter().map(|r| r.to_string()).collect();
+        format!(
+            "nwc://{}?relay={}",
+            self.pool.uri.public_key,
+            relays.join(",")
+        )
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        let nwc = self.pool.checkout().await?;
+        match nwc.get_info().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                nwc.discard();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        comment: Option<&str>,
+        expiry: Option<u64>,
+    ) -> Result<String> {
+        // NWC has no place for the comment in make_invoice, so we only log it.
+        if let Some(comment) = comment {
+            tracing::info!(comment = comment, "payer comment received.");
+        }
+        let req = make_invoice_request(amount_msat, description, expiry, self.use_description_hash);
+        let nwc = self.pool.checkout().await?;
+        let start = Instant::now();
+        let res = nwc.make_invoice(req).await;
+        tracing::info!(
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            ok = res.is_ok(),
+            "NWC make_invoice returned."
+        );
+        match res {
+            Ok(res) => Ok(res.invoice),
+            Err(e) => {
+                // the connection may be broken, don't reuse it.
+                nwc.discard();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn lookup_invoice(&self, invoice: &str) -> Result<InvoiceStatus> {
+        let req = LookupInvoiceRequest {
+            payment_hash: None,
+            invoice: Some(invoice.to_string()),
         };
-        let invoice = self.nwc.make_invoice(req).await?.invoice;
-        Ok(invoice)
+        let nwc = self.pool.checkout().await?;
+        match nwc.lookup_invoice(req).await {
+            Ok(res) if res.settled_at.is_some() => Ok(InvoiceStatus::Paid {
+                preimage: res.preimage,
+            }),
+            Ok(_) => Ok(InvoiceStatus::Unpaid),
+            Err(e) => {
+                nwc.discard();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn get_balance(&self) -> Result<u64> {
+        let nwc = self.pool.checkout().await?;
+        match nwc.get_balance().await {
+            Ok(balance_msat) => Ok(balance_msat),
+            Err(e) => {
+                nwc.discard();
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn pay_invoice(&self, invoice: &str) -> Result<()> {
+        let nwc = self.pool.checkout().await?;
+        match nwc.pay_invoice(PayInvoiceRequest::new(invoice)).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                nwc.discard();
+                Err(e.into())
+            }
+        }
     }
 }
 
 pub struct NwcInvoiceCreator {
-    nwc: NWC,
+    pool: NwcPool,
+    use_description_hash: bool,
 }
 
 impl NwcInvoiceCreator {
-    pub fn new(nwc_str: &str) -> Result<Self> {
+    /// At most `pool_size` connections to the wallet are kept and used concurrently.
+    /// Without `use_description_hash`, the invoices get the description itself rather than its
+    /// hash, which LUD-06 doesn't allow, for the wallets which don't support hashes.
+    pub fn new(nwc_str: &str, pool_size: usize, use_description_hash: bool) -> Result<Self> {
         let uri = NostrWalletConnectURI::from_str(nwc_str)?;
-        Ok(NwcInvoiceCreator { nwc: NWC::new(uri) })
+        Ok(NwcInvoiceCreator {
+            pool: NwcPool::new(uri, pool_size),
+            use_description_hash,
+        })
+    }
+}
+
+fn make_invoice_request(
+    amount_msat: u64,
+    description: &str,
+    expiry: Option<u64>,
+    use_description_hash: bool,
+) -> MakeInvoiceRequest {
+    let (description, description_hash) = if use_description_hash {
+        (
+            None,
+            Some(format!("{}", Sha256::hash(description.as_bytes()))),
+        )
+    } else {
+        (Some(description.to_string()), None)
+    };
+    MakeInvoiceRequest {
+        amount: amount_msat,
+        description,
+        description_hash,
+        expiry,
+    }
+}
+
+/// A bounded pool of live NWC clients for the same URI, so that the relay connections are
+/// reused across requests. Connections are opened lazily.
+struct NwcPool {
+    uri: NostrWalletConnectURI,
+    idle: Mutex<Vec<NWC>>,
+    permits: Semaphore,
+}
+
+impl NwcPool {
+    fn new(uri: NostrWalletConnectURI, size: usize) -> NwcPool {
+        NwcPool {
+            uri,
+            idle: Mutex::new(Vec::with_capacity(size)),
+            permits: Semaphore::new(size.max(1)),
+        }
+    }
+
+    /// Waits until a client is available. It goes back to the pool when the returned guard is dropped.
+    async fn checkout(&self) -> Result<PooledNwc<'_>> {
+        let permit = self.permits.acquire().await?;
+        let nwc = self
+            .idle
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| NWC::new(self.uri.clone()));
+        Ok(PooledNwc {
+            pool: self,
+            nwc: Some(nwc),
+            _permit: permit,
+        })
+    }
+}
+
+struct PooledNwc<'a> {
+    pool: &'a NwcPool,
+    nwc: Option<NWC>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl PooledNwc<'_> {
+    /// Closes the client instead of returning it to the pool.
+    fn discard(mut self) {
+        if let Some(nwc) = self.nwc.take() {
+            tokio::spawn(nwc.shutdown());
+        }
+    }
+}
+
+impl Deref for PooledNwc<'_> {
+    type Target = NWC;
+
+    fn deref(&self) -> &NWC {
+        // only taken by `discard` and `drop`.
+        self.nwc.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledNwc<'_> {
+    fn drop(&mut self) {
+        if let Some(nwc) = self.nwc.take() {
+            self.pool
+                .idle
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(nwc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c";
+
+    fn idle_count(pool: &NwcPool) -> usize {
+        pool.idle.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn pool_reuses_returned_clients() -> Result<()> {
+        let pool = NwcPool::new(NostrWalletConnectURI::from_str(URI)?, 2);
+        {
+            let _a = pool.checkout().await?;
+            let _b = pool.checkout().await?;
+            assert_eq!(pool.permits.available_permits(), 0);
+        }
+        assert_eq!(idle_count(&pool), 2);
+
+        let _a = pool.checkout().await?;
+        assert_eq!(idle_count(&pool), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn make_invoice_request_hashes_description() {
+        let req = make_invoice_request(21_000, "metadata", Some(600), true);
+        assert_eq!(req.description, None);
+        assert_eq!(
+            req.description_hash,
+            Some(Sha256::hash(b"metadata").to_string())
+        );
+
+        let req = make_invoice_request(21_000, "metadata", Some(600), false);
+        assert_eq!(req.description.as_deref(), Some("metadata"));
+        assert_eq!(req.description_hash, None);
+    }
+
+    #[tokio::test]
+    async fn pool_drops_discarded_clients() -> Result<()> {
+        let pool = NwcPool::new(NostrWalletConnectURI::from_str(URI)?, 2);
+        pool.checkout().await?.discard();
+        assert_eq!(idle_count(&pool), 0);
+        assert_eq!(pool.permits.available_permits(), 2);
+        Ok(())
     }
 }
@@ -0,0 +1,166 @@
+use super::{InvoiceCreator, InvoiceStatus};
+use crate::config::RetryPolicy;
+use anyhow::Result;
+use futures::future::join_all;
+use std::sync::{Arc, Mutex};
+use tracing::Instrument;
+
+/// Tries the wrapped creators in order until one of them creates the invoice. Each one is retried
+/// with backoff according to the retry policy, before moving on to the next.
+/// It remembers which creator succeeded, so that the invoice can be looked up afterwards. It's meant
+/// to be built for a single invoice.
+pub struct RetryableInvoiceCreator {
+    creators: Vec<Arc<dyn InvoiceCreator>>,
+    retry_policy: RetryPolicy,
+    succeeded: Mutex<Option<usize>>,
+}
+
+impl RetryableInvoiceCreator {
+    pub fn new(
+        creators: Vec<Arc<dyn InvoiceCreator>>,
+        retry_policy: RetryPolicy,
+    ) -> RetryableInvoiceCreator {
+        RetryableInvoiceCreator {
+            creators,
+            retry_policy,
+            succeeded: Mutex::new(None),
+        }
+    }
+
+    /// Index of the creator that created the last invoice, if any.
+    pub fn succeeded(&self) -> Option<usize> {
+        *self.succeeded.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn succeeded_creator(&self) -> Option<&Arc<dyn InvoiceCreator>> {
+        self.succeeded().map(|i| &self.creators[i])
+    }
+}
+
+#[async_trait::async_trait]
+impl InvoiceCreator for RetryableInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        self.succeeded_creator()
+            .map_or("retryable", |creator| creator.backend())
+    }
+
+    fn endpoint(&self) -> String {
+        self.succeeded_creator()
+            .map_or_else(|| self.backend().to_string(), |creator| creator.endpoint())
+    }
+
+    /// Healthy as long as one of the creators is.
+    async fn check_health(&self) -> Result<()> {
+        let results = join_all(self.creators.iter().map(|creator| creator.check_health())).await;
+        let mut last_err = anyhow::anyhow!("no backend to create the invoice");
+        for res in results {
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Returns the error of the last attempt when all of them fail.
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        comment: Option<&str>,
+        expiry: Option<u64>,
+    ) -> Result<String> {
+        let mut last_err = None;
+        for (i, creator) in self.creators.iter().enumerate() {
+            for attempt in 0..self.retry_policy.nwc_retry_attempts {
+                if attempt > 0 {
+                    tokio::time::sleep(self.retry_policy.backoff(attempt - 1)).await;
+                }
+                let res = creator
+                    .create_invoice(amount_msat, description, comment, expiry)
+                    .instrument(tracing::info_span!(
+                        "backend_create_invoice",
+                        backend = creator.backend(),
+                        attempt
+                    ))
+                    .await;
+                match res {
+                    Ok(invoice) => {
+                        *self.succeeded.lock().unwrap_or_else(|e| e.into_inner()) = Some(i);
+                        return Ok(invoice);
+                    }
+                    Err(e) => {
+                        tracing::warn!(backend = creator.backend(), attempt = attempt + 1, error = %e, "failed to create invoice.");
+                        last_err = Some(e);
+                    }
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no backend to create the invoice")))
+    }
+
+    async fn lookup_invoice(&self, invoice: &str) -> Result<InvoiceStatus> {
+        match self.succeeded_creator() {
+            Some(creator) => creator.lookup_invoice(invoice).await,
+            None => anyhow::bail!("no invoice created yet"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockFailCreator, MockInvoiceCreator};
+
+    fn retry_policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            nwc_retry_attempts: attempts,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_then_falls_over_to_next_creator() {
+        let creator = RetryableInvoiceCreator::new(
+            vec![
+                Arc::new(MockFailCreator),
+                Arc::new(MockInvoiceCreator::new(vec![
+                    Err(anyhow::anyhow!("timeout")),
+                    Err(anyhow::anyhow!("timeout")),
+                    Ok("lnbc1third".to_string()),
+                ])),
+            ],
+            retry_policy(3),
+        );
+        assert!(creator.check_health().await.is_ok());
+        assert_eq!(creator.backend(), "retryable");
+
+        let invoice = creator.create_invoice(1000, "", None, None).await.unwrap();
+        assert_eq!(invoice, "lnbc1third");
+        assert_eq!(creator.succeeded(), Some(1));
+        assert_eq!(creator.backend(), "mock");
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_when_all_attempts_fail() {
+        let creator = RetryableInvoiceCreator::new(
+            vec![Arc::new(MockInvoiceCreator::new(vec![
+                Err(anyhow::anyhow!("first")),
+                Err(anyhow::anyhow!("second")),
+                Ok("lnbc1third".to_string()),
+            ]))],
+            retry_policy(2),
+        );
+        let err = creator
+            .create_invoice(1000, "", None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "second");
+        assert_eq!(creator.succeeded(), None);
+
+        let creator = RetryableInvoiceCreator::new(vec![], retry_policy(2));
+        assert!(creator.create_invoice(1000, "", None, None).await.is_err());
+        assert!(creator.check_health().await.is_err());
+    }
+}
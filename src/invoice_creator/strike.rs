@@ -0,0 +1,354 @@
+use super::{InvoiceCreator, InvoiceStatus};
+use crate::config::StrikeConfig;
+use anyhow::{Context, Result};
+use bitcoin_hashes::Sha256;
+use dashmap::DashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Strike API: https://docs.strike.me/walkthrough/receiving-payments
+// An invoice is created first, and then quoted, which is what gives the bolt11 invoice. Strike
+// settles it asynchronously, its state going from UNPAID to PAID, which `lookup_invoice` polls.
+#[async_trait::async_trait]
+impl InvoiceCreator for StrikeInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        "strike"
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        self.balances().await?;
+        Ok(())
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        comment: Option<&str>,
+        _expiry: Option<u64>,
+    ) -> Result<String> {
+        if let Some(comment) = comment {
+            tracing::info!(comment = comment, "payer comment received.");
+        }
+        if !amount_msat.is_multiple_of(1000) {
+            anyhow::bail!("Strike only takes whole sats, got {} msat", amount_msat);
+        }
+        let req = InvoiceRequest {
+            correlation_id: uuid::Uuid::new_v4().to_string(),
+            // shown in the Strike app, the wallet of the payer only sees the description hash.
+            description: if self.use_description_hash {
+                "thor"
+            } else {
+                description
+            },
+            amount: Amount {
+                currency: self.currency.clone(),
+                amount: msat_to_btc(amount_msat),
+            },
+        };
+        let invoice: InvoiceResponse = self
+            .call(self.client.post(self.url("invoices")).json(&req))
+            .await?;
+        let req = QuoteRequest {
+            // only put the sha256 of description in the invoice, as LUD-06 requires. Otherwise
+            // the invoice gets the description of the Strike invoice.
+            description_hash: self
+                .use_description_hash
+                .then(|| Sha256::hash(description.as_bytes()).to_string()),
+        };
+        let quote: QuoteResponse = self
+            .call(
+                self.client
+                    .post(self.url(&format!("invoices/{}/quote", invoice.invoice_id)))
+                    .json(&req),
+            )
+            .await?;
+        self.remember(&quote, invoice.invoice_id);
+        Ok(quote.ln_invoice)
+    }
+
+    async fn get_balance(&self) -> Result<u64> {
+        let balances = self.balances().await?;
+        balances
+            .iter()
+            .find(|balance| balance.currency == "BTC")
+            .map_or(Ok(0), |balance| btc_to_msat(&balance.available))
+    }
+
+    async fn lookup_invoice(&self, invoice: &str) -> Result<InvoiceStatus> {
+        let invoice_id = self
+            .invoice_ids
+            .get(invoice)
+            .map(|entry| entry.0.clone())
+            .context("invoice not created by this Strike account, or expired")?;
+        let res: InvoiceResponse = self
+            .call(
+                self.client
+                    .get(self.url(&format!("invoices/{}", invoice_id))),
+            )
+            .await?;
+        if res.state != "PAID" {
+            return Ok(InvoiceStatus::Unpaid);
+        }
+        self.invoice_ids.remove(invoice);
+        // Strike doesn't tell the preimage.
+        Ok(InvoiceStatus::Paid { preimage: None })
+    }
+}
+
+/// A Strike account, for users without a Lightning node of their own.
+pub struct StrikeInvoiceCreator {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: String,
+    currency: String,
+    // Strike looks invoices up by its own ids, which are kept until the invoice expires.
+    invoice_ids: DashMap<String, (String, Instant)>,
+    use_description_hash: bool,
+}
+
+impl StrikeInvoiceCreator {
+    pub fn new(config: &StrikeConfig, use_description_hash: bool) -> Result<Self> {
+        Ok(StrikeInvoiceCreator {
+            client: reqwest::Client::builder().build()?,
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            api_key: config.api_key.clone(),
+            currency: config.currency.clone(),
+            invoice_ids: DashMap::new(),
+            use_description_hash,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.api_url, path)
+    }
+
+    async fn call<T: DeserializeOwned>(&self, builder: reqwest::RequestBuilder) -> Result<T> {
+        let res = builder.bearer_auth(&self.api_key).send().await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            anyhow::bail!("Strike returned {}: {}", status, body);
+        }
+        Ok(res.json().await?)
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>> {
+        self.call(self.client.get(self.url("balances"))).await
+    }
+
+    fn remember(&self, quote: &QuoteResponse, invoice_id: String) {
+        let now = Instant::now();
+        self.invoice_ids
+            .retain(|_, (_, expires_at)| *expires_at > now);
+        let expires_at = now + Duration::from_secs(quote.expiration_in_sec);
+        self.invoice_ids
+            .insert(quote.ln_invoice.clone(), (invoice_id, expires_at));
+    }
+}
+
+/// Strike takes amounts as decimal strings, in BTC for bitcoin.
+fn msat_to_btc(amount_msat: u64) -> String {
+    let sats = amount_msat / 1000;
+    format!("{}.{:08}", sats / 100_000_000, sats % 100_000_000)
+}
+
+fn btc_to_msat(btc: &str) -> Result<u64> {
+    let invalid = || anyhow::anyhow!("invalid BTC amount {:?}", btc);
+    let (whole, fraction) = btc.split_once('.').unwrap_or((btc, ""));
+    if fraction.len() > 11 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let whole: u64 = whole.parse().map_err(|_| invalid())?;
+    let fraction: u64 = format!("{:0<11}", fraction)
+        .parse()
+        .map_err(|_| invalid())?;
+    whole
+        .checked_mul(100_000_000_000)
+        .and_then(|msat| msat.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvoiceRequest<'a> {
+    correlation_id: String,
+    description: &'a str,
+    amount: Amount,
+}
+
+#[derive(Debug, Serialize)]
+struct Amount {
+    currency: String,
+    amount: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InvoiceResponse {
+    invoice_id: String,
+    state: String, // UNPAID, PENDING, PAID or CANCELLED
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuoteRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuoteResponse {
+    ln_invoice: String,
+    expiration_in_sec: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Balance {
+    currency: String,
+    available: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+        match headers["authorization"] == "Bearer key" {
+            true => Ok(()),
+            false => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    async fn create(
+        headers: HeaderMap,
+        Json(req): Json<serde_json::Value>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        authorized(&headers)?;
+        assert_eq!(req["amount"]["amount"], "0.00000002");
+        Ok(Json(
+            serde_json::json!({"invoiceId": "inv-1", "state": "UNPAID"}),
+        ))
+    }
+
+    async fn quote(
+        headers: HeaderMap,
+        Path(invoice_id): Path<String>,
+        Json(req): Json<serde_json::Value>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        authorized(&headers)?;
+        assert_eq!(invoice_id, "inv-1");
+        assert_eq!(
+            req["descriptionHash"],
+            Sha256::hash(b"metadata").to_string()
+        );
+        Ok(Json(
+            serde_json::json!({"lnInvoice": "lnbc20n1strike", "expirationInSec": 3600}),
+        ))
+    }
+
+    async fn invoice(
+        State(paid): State<Arc<AtomicBool>>,
+        headers: HeaderMap,
+        Path(invoice_id): Path<String>,
+    ) -> Result<Json<serde_json::Value>, StatusCode> {
+        authorized(&headers)?;
+        let state = if paid.load(Ordering::SeqCst) {
+            "PAID"
+        } else {
+            "UNPAID"
+        };
+        Ok(Json(
+            serde_json::json!({"invoiceId": invoice_id, "state": state}),
+        ))
+    }
+
+    async fn balances(headers: HeaderMap) -> Result<Json<serde_json::Value>, StatusCode> {
+        authorized(&headers)?;
+        Ok(Json(
+            serde_json::json!([{"currency": "BTC", "available": "0.00000021"}]),
+        ))
+    }
+
+    #[tokio::test]
+    async fn create_and_look_up_invoice() -> Result<()> {
+        let paid = Arc::new(AtomicBool::new(false));
+        let app = Router::new()
+            .route("/v1/invoices", post(create))
+            .route("/v1/invoices/{invoice_id}/quote", post(quote))
+            .route("/v1/invoices/{invoice_id}", get(invoice))
+            .route("/v1/balances", get(balances))
+            .with_state(paid.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let creator = StrikeInvoiceCreator::new(
+            &StrikeConfig {
+                api_key: "key".to_string(),
+                currency: "BTC".to_string(),
+                api_url: format!("http://{}/v1/", addr),
+            },
+            true,
+        )?;
+        let invoice = creator.create_invoice(2000, "metadata", None, None).await?;
+        assert_eq!(invoice, "lnbc20n1strike");
+        assert_eq!(
+            creator.lookup_invoice(&invoice).await?,
+            InvoiceStatus::Unpaid
+        );
+        paid.store(true, Ordering::SeqCst);
+        assert_eq!(
+            creator.lookup_invoice(&invoice).await?,
+            InvoiceStatus::Paid { preimage: None }
+        );
+        assert_eq!(creator.get_balance().await?, 21_000);
+
+        let creator = StrikeInvoiceCreator::new(
+            &StrikeConfig {
+                api_key: "wrong".to_string(),
+                currency: "BTC".to_string(),
+                api_url: format!("http://{}/v1", addr),
+            },
+            true,
+        )?;
+        assert!(creator.check_health().await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn converts_btc_amounts() {
+        assert_eq!(msat_to_btc(1_500_000), "0.00001500");
+        assert_eq!(msat_to_btc(210_000_000_000), "2.10000000");
+        assert_eq!(btc_to_msat("0.00001500").unwrap(), 1_500_000);
+        assert_eq!(btc_to_msat("2.1").unwrap(), 210_000_000_000);
+        assert_eq!(btc_to_msat("3").unwrap(), 300_000_000_000);
+        assert!(btc_to_msat("0.1234567890123").is_err());
+        assert!(btc_to_msat("-1").is_err());
+        assert!(btc_to_msat("1.-5").is_err());
+    }
+
+    #[test]
+    fn invoice_request_json() {
+        let req = InvoiceRequest {
+            correlation_id: "42".to_string(),
+            description: "thor",
+            amount: Amount {
+                currency: "BTC".to_string(),
+                amount: msat_to_btc(21_000),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"correlationId":"42","description":"thor","amount":{"currency":"BTC","amount":"0.00000021"}}"#
+        );
+    }
+}
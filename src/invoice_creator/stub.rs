@@ -0,0 +1,126 @@
+use super::{InvoiceCreator, InvoiceStatus};
+use anyhow::Result;
+use bitcoin::hashes::{Hash, sha256};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Signs invoices with a random node key, enabled by `stub = true`. The invoices look real to
+/// wallets, but nobody can pay them. Only for development, without a Lightning node.
+pub struct StubInvoiceCreator {
+    node_key: SecretKey,
+    use_description_hash: bool,
+}
+
+impl StubInvoiceCreator {
+    pub fn new(use_description_hash: bool) -> Self {
+        // a random 32 bytes string is out of the curve order with a negligible probability.
+        let node_key =
+            std::iter::repeat_with(|| SecretKey::from_slice(&rand::random::<[u8; 32]>()))
+                .find_map(Result::ok)
+                .expect("an infinite iterator");
+        StubInvoiceCreator {
+            node_key,
+            use_description_hash,
+        }
+    }
+}
+
+impl Default for StubInvoiceCreator {
+    fn default() -> Self {
+        StubInvoiceCreator::new(true)
+    }
+}
+
+#[async_trait::async_trait]
+impl InvoiceCreator for StubInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        "stub"
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_invoice(
+        &self,
+        amount_msat: u64,
+        description: &str,
+        comment: Option<&str>,
+        expiry: Option<u64>,
+    ) -> Result<String> {
+        if let Some(comment) = comment {
+            tracing::info!(comment = comment, "payer comment received.");
+        }
+        // nobody knows the preimage, so the invoice can't be paid.
+        let payment_hash = sha256::Hash::hash(&rand::random::<[u8; 32]>());
+        let builder = InvoiceBuilder::new(Currency::Bitcoin);
+        let builder = match self.use_description_hash {
+            true => builder.description_hash(sha256::Hash::hash(description.as_bytes())),
+            false => builder.description(description.to_string()),
+        };
+        let mut builder = builder
+            .payment_hash(payment_hash)
+            .payment_secret(PaymentSecret(rand::random()))
+            .duration_since_epoch(SystemTime::now().duration_since(UNIX_EPOCH)?)
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(amount_msat);
+        if let Some(expiry) = expiry {
+            builder = builder.expiry_time(Duration::from_secs(expiry));
+        }
+        let secp = Secp256k1::new();
+        let invoice = builder
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &self.node_key))
+            .map_err(|e| anyhow::anyhow!("failed to build stub invoice: {}", e))?;
+        Ok(invoice.to_string())
+    }
+
+    async fn get_balance(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    async fn lookup_invoice(&self, _invoice: &str) -> Result<InvoiceStatus> {
+        Ok(InvoiceStatus::Unpaid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescriptionRef};
+
+    #[tokio::test]
+    async fn creates_parsable_invoice() -> Result<()> {
+        let creator = StubInvoiceCreator::default();
+        let invoice = creator
+            .create_invoice(21_000, "metadata", None, Some(600))
+            .await?;
+        let invoice: Bolt11Invoice = invoice.parse().unwrap();
+        assert_eq!(invoice.amount_milli_satoshis(), Some(21_000));
+        assert_eq!(invoice.expiry_time(), Duration::from_secs(600));
+        match invoice.description() {
+            Bolt11InvoiceDescriptionRef::Hash(hash) => {
+                assert_eq!(hash.0, sha256::Hash::hash(b"metadata"))
+            }
+            Bolt11InvoiceDescriptionRef::Direct(_) => panic!("expected a description hash"),
+        }
+        assert!(invoice.check_signature().is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn creates_invoice_with_plain_description() -> Result<()> {
+        let creator = StubInvoiceCreator::new(false);
+        let invoice = creator
+            .create_invoice(21_000, "metadata", None, None)
+            .await?;
+        let invoice: Bolt11Invoice = invoice.parse().unwrap();
+        match invoice.description() {
+            Bolt11InvoiceDescriptionRef::Direct(description) => {
+                assert_eq!(description.to_string(), "metadata")
+            }
+            Bolt11InvoiceDescriptionRef::Hash(_) => panic!("expected a plain description"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use tokio::sync::OnceCell;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS invoice_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    amount_msat INTEGER NOT NULL,
+    backend TEXT NOT NULL,
+    success INTEGER NOT NULL,
+    bolt11 TEXT,
+    error TEXT,
+    created_at INTEGER NOT NULL -- unix timestamp in milliseconds
+);
+CREATE INDEX IF NOT EXISTS invoice_log_username_created_at ON invoice_log (username, created_at);
+";
+
+/// Records every invoice creation attempt to SQLite, for auditing and analytics.
+pub struct InvoiceLog {
+    pool: SqlitePool,
+    migrated: OnceCell<()>,
+}
+
+/// One invoice creation attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvoiceAttempt<'a> {
+    pub username: &'a str,
+    pub amount_msat: u64,
+    pub backend: &'a str,
+    /// the bolt11 invoice on success, or the error message on failure.
+    pub result: Result<&'a str, &'a str>,
+}
+
+impl InvoiceLog {
+    /// The database file is created if missing. Connections are opened lazily.
+    pub fn new(db_path: &str) -> InvoiceLog {
+        let options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .create_if_missing(true);
+        InvoiceLog {
+            pool: SqlitePoolOptions::new().connect_lazy_with(options),
+            migrated: OnceCell::new(),
+        }
+    }
+
+    /// Creates the schema if needed. Also done before the first write.
+    pub async fn migrate(&self) -> Result<()> {
+        self.migrated
+            .get_or_try_init(|| async {
+                sqlx::raw_sql(SCHEMA).execute(&self.pool).await?;
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn record(&self, attempt: &InvoiceAttempt<'_>) -> Result<()> {
+        self.migrate().await?;
+        let (bolt11, error) = match attempt.result {
+            Ok(bolt11) => (Some(bolt11), None),
+            Err(error) => (None, Some(error)),
+        };
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis() as i64;
+        sqlx::query(
+            "INSERT INTO invoice_log (username, amount_msat, backend, success, bolt11, error, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(attempt.username)
+        .bind(attempt.amount_msat as i64)
+        .bind(attempt.backend)
+        .bind(attempt.result.is_ok())
+        .bind(bolt11)
+        .bind(error)
+        .bind(created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Row;
+
+    #[tokio::test]
+    async fn record_attempts() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("thor-invoice-log-{}.db", std::process::id()));
+        let log = InvoiceLog::new(path.to_str().unwrap());
+        log.record(&InvoiceAttempt {
+            username: "alice",
+            amount_msat: 1000,
+            backend: "nwc",
+            result: Err("timed out"),
+        })
+        .await?;
+        log.record(&InvoiceAttempt {
+            username: "alice",
+            amount_msat: 1000,
+            backend: "nwc",
+            result: Ok("lnbc1test"),
+        })
+        .await?;
+
+        let rows = sqlx::query("SELECT success, bolt11, error FROM invoice_log ORDER BY id")
+            .fetch_all(&log.pool)
+            .await?;
+        assert_eq!(rows.len(), 2);
+        assert!(!rows[0].get::<bool, _>("success"));
+        assert_eq!(
+            rows[0].get::<Option<String>, _>("error").as_deref(),
+            Some("timed out")
+        );
+        assert!(rows[1].get::<bool, _>("success"));
+        assert_eq!(
+            rows[1].get::<Option<String>, _>("bolt11").as_deref(),
+            Some("lnbc1test")
+        );
+
+        log.pool.close().await;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
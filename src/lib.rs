@@ -1,4 +1,31 @@
+//! thor serves lightning addresses, creating the invoices on the users' own wallets.
+//!
+//! Besides the `thor` binary, it can be embedded in other applications: load a [`Config`], and
+//! serve it with [`run_http_server`], or mount [`http_server::router`] over an [`AppState`] in an
+//! existing axum app. Custom backends implement [`InvoiceCreator`].
+
+pub mod avatar;
 pub mod config;
+pub mod config_watcher;
 pub mod error;
+pub mod exchange_rate;
 pub mod http_server;
 pub mod invoice_creator;
+pub mod invoice_log;
+pub mod metrics;
+pub mod payer_data;
+pub mod rate_limit;
+pub mod shutdown;
+pub mod success_action;
+#[cfg(test)]
+pub mod test_utils;
+pub mod webhook;
+pub mod zap;
+
+pub use config::Config;
+pub use error::{ErrorCode, HttpError, Lud06Error};
+pub use http_server::{AppState, SharedState, run_http_server};
+pub use invoice_creator::{
+    BackendInvoiceCreatorFactory, InvoiceCreator, InvoiceCreatorFactory, InvoiceStatus,
+    NwcInvoiceCreator, UserInvoiceCreators,
+};
@@ -1,41 +1,292 @@
 use anyhow::Result;
-use std::env::args;
-use thor::config::Config;
+use clap::{Parser, Subcommand};
+use futures::future::join_all;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thor::config::{Config, LogFormat, LogRotation, UserConfig, display_paths};
 use thor::http_server::run_http_server;
+use thor::invoice_creator::{BackendInvoiceCreatorFactory, InvoiceCreatorFactory};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry};
+
+/// How long `--dry-run` waits for each backend.
+const DRY_RUN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Path of the config file to run the server with. Several files, separated by commas, are
+    /// loaded as one: later ones override the settings of earlier ones, and add to their users.
+    #[arg(default_value = "config.toml", value_delimiter = ',')]
+    config: Vec<PathBuf>,
+    /// Same as the positional argument, e.g. `--config base.toml,users.toml`.
+    #[arg(long = "config", value_delimiter = ',', conflicts_with = "config")]
+    config_flag: Option<Vec<PathBuf>>,
+    /// Validate the config file and check that every backend is reachable, without serving.
+    /// Exits with an error if any backend is unreachable.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Validate the config file and exit.
+    Validate {
+        #[arg(default_value = "config.toml", value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+    /// List the configured users and their backends.
+    ListUsers {
+        #[arg(default_value = "config.toml", value_delimiter = ',')]
+        config: Vec<PathBuf>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config_path = match args().nth(1) {
-        Some(path) => path,
-        None => "config.toml".to_string(),
-    };
-    println!("loading configuration from {}", config_path);
-    let config = Config::load_from_toml(config_path.as_ref())?;
+    let cli = Cli::parse();
+    let config = cli.config_flag.unwrap_or(cli.config);
+    match cli.command {
+        Some(Command::Validate { config }) => validate(&config),
+        Some(Command::ListUsers { config }) => list_users(&config),
+        None if cli.dry_run => dry_run(&config).await,
+        None => run(&config).await,
+    }
+}
 
-    let format = tracing_subscriber::fmt::format()
-        .with_file(true)
-        .with_line_number(true)
-        .with_target(false)
-        .compact();
+fn load_config(config_paths: &[PathBuf]) -> Result<Config> {
+    let config_paths: Vec<&Path> = config_paths.iter().map(PathBuf::as_path).collect();
+    Config::load_from_tomls(&config_paths)
+}
+
+fn validate(config_paths: &[PathBuf]) -> Result<()> {
+    match load_config(config_paths) {
+        Ok(_) => {
+            println!("{} is valid", display_paths(config_paths));
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{} is invalid: {:#}", display_paths(config_paths), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Users of every domain, by their lightning address.
+fn users_by_address(config: &Config) -> Vec<(String, &UserConfig)> {
+    config
+        .users
+        .iter()
+        .map(|user| (format!("{}@{}", user.name, config.server.domain), user))
+        .chain(config.domains.iter().flat_map(|domain_config| {
+            domain_config
+                .users
+                .iter()
+                .map(|user| (format!("{}@{}", user.name, domain_config.domain), user))
+        }))
+        .collect()
+}
+
+fn list_users(config_paths: &[PathBuf]) -> Result<()> {
+    let config = load_config(config_paths)?;
+    let users = users_by_address(&config);
+    let address_width = users
+        .iter()
+        .map(|(address, _)| address.len())
+        .chain(std::iter::once("ADDRESS".len()))
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:<address_width$}  {:>4}  {:>8}  {:>8}  {:>8}  {:>11}  {:>6}  {:>18}  {:>18}",
+        "ADDRESS",
+        "NWCS",
+        "CLN REST",
+        "LND GRPC",
+        "LND REST",
+        "ECLAIR REST",
+        "STRIKE",
+        "MIN SENDABLE(msat)",
+        "MAX SENDABLE(msat)"
+    );
+    for (address, user) in &users {
+        println!(
+            "{:<address_width$}  {:>4}  {:>8}  {:>8}  {:>8}  {:>11}  {:>6}  {:>18}  {:>18}",
+            address,
+            user.nwcs.len(),
+            usize::from(user.cln_rest.is_some()),
+            usize::from(user.lnd_grpc.is_some()),
+            usize::from(user.lnd_rest.is_some()),
+            usize::from(user.eclair_rest.is_some()),
+            usize::from(user.strike.is_some()),
+            user.min_sendable_msat,
+            user.max_sendable_msat,
+        );
+    }
+    Ok(())
+}
+
+async fn dry_run(config_paths: &[PathBuf]) -> Result<()> {
+    let config = load_config(config_paths)?;
+    println!("{} is valid", display_paths(config_paths));
 
-    let stdout_layer = tracing_subscriber::fmt::layer()
-        .event_format(format.clone())
-        .with_writer(std::io::stdout)
-        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
+    let factory = BackendInvoiceCreatorFactory;
+    let mut backends = vec![];
+    for (address, user) in users_by_address(&config) {
+        let creators = factory.create_from_config(user)?;
+        for creator in creators.primary.into_iter().chain(creators.fallback) {
+            backends.push((address.clone(), creator));
+        }
+    }
+    let checks = backends.iter().map(|(_, creator)| async move {
+        match tokio::time::timeout(DRY_RUN_TIMEOUT, creator.check_health()).await {
+            Ok(res) => res,
+            Err(_) => Err(anyhow::anyhow!("timed out after {:?}", DRY_RUN_TIMEOUT)),
+        }
+    });
+    let results = join_all(checks).await;
 
-    let file_appender = tracing_appender::rolling::daily(&config.server.log_dir, "thor.log");
+    let mut unreachable = 0;
+    for ((address, creator), res) in backends.iter().zip(results) {
+        let status = match res {
+            Ok(()) => "ok".to_string(),
+            Err(e) => {
+                unreachable += 1;
+                format!("unreachable: {}", e)
+            }
+        };
+        println!(
+            "{}  {}  {}  {}",
+            address,
+            creator.backend(),
+            creator.endpoint(),
+            status
+        );
+    }
+    if unreachable > 0 {
+        eprintln!("{} of {} backends unreachable", unreachable, backends.len());
+        std::process::exit(1);
+    }
+    println!("all {} backends reachable", backends.len());
+    Ok(())
+}
+
+async fn run(config_paths: &[PathBuf]) -> Result<()> {
+    println!("loading configuration from {}", display_paths(config_paths));
+    let config = load_config(config_paths)?;
+
+    let stdout_layer = log_layer(config.server.stdout_log_format, std::io::stdout, true)
+        .with_filter(config.server.stdout_log_level()?)
+        .boxed();
+
+    let log_dir = &config.server.log_dir;
+    let file_appender = match config.server.log_rotation {
+        LogRotation::Hourly => tracing_appender::rolling::hourly(log_dir, "thor.log"),
+        LogRotation::Daily => tracing_appender::rolling::daily(log_dir, "thor.log"),
+        LogRotation::Never => tracing_appender::rolling::never(log_dir, "thor.log"),
+    };
     let (nonblocking_appender, _guard) = tracing_appender::non_blocking(file_appender);
-    let file_layer = tracing_subscriber::fmt::layer()
-        .event_format(format.json())
-        .with_writer(nonblocking_appender)
-        .with_filter(tracing_subscriber::filter::LevelFilter::INFO);
-
-    let subscriber = tracing_subscriber::registry()
-        .with(stdout_layer)
-        .with(file_layer);
+    let file_layer = log_layer(config.server.file_log_format, nonblocking_appender, false)
+        .with_filter(config.server.file_log_level()?)
+        .boxed();
+
+    let mut layers = vec![stdout_layer, file_layer];
+    let tracer_provider = match &config.server.opentelemetry {
+        Some(opentelemetry) => {
+            let tracer_provider = new_tracer_provider(&opentelemetry.otlp_endpoint)?;
+            let tracer = tracer_provider.tracer("thor");
+            layers.push(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(LevelFilter::INFO)
+                    .boxed(),
+            );
+            Some(tracer_provider)
+        }
+        None => None,
+    };
+    // tokio only reports its tasks when built with `RUSTFLAGS="--cfg tokio_unstable"`, the console
+    // shows nothing otherwise. The layer is added to ours, `console_subscriber::init` would take
+    // the place of the global subscriber.
+    #[cfg(feature = "tokio-console")]
+    layers.push(console_subscriber::spawn().boxed());
+    // the layers are boxed as their types depend on the formats, hence a vec instead of nested layers.
+    let subscriber = tracing_subscriber::registry().with(layers);
     tracing::subscriber::set_global_default(subscriber)?;
+    log_startup_banner(&config);
 
-    run_http_server(&config).await?;
-    Ok(())
+    let res = run_http_server(
+        &config,
+        config_paths,
+        Arc::new(BackendInvoiceCreatorFactory),
+    )
+    .await;
+    // spans are exported in batches, flush what's left.
+    if let Some(tracer_provider) = tracer_provider
+        && let Err(e) = tracer_provider.shutdown()
+    {
+        eprintln!("failed to flush traces: {}", e);
+    }
+    res
+}
+
+/// Summarizes the loaded config, for operators to confirm that it's the one they meant.
+fn log_startup_banner(config: &Config) {
+    let users: Vec<&UserConfig> = config
+        .users
+        .iter()
+        .chain(config.domains.iter().flat_map(|domain| &domain.users))
+        .collect();
+    let nwc_backends: usize = users.iter().map(|user| user.nwcs.len()).sum();
+    tracing::info!(
+        users = users.len(),
+        nwc_backends,
+        listen_addrs = ?config.server.listen_addrs,
+        domain = config.server.domain,
+        log_dir = config.server.log_dir,
+        tls = config.server.tls.is_some(),
+        "starting thor."
+    );
+}
+
+fn new_tracer_provider(otlp_endpoint: &str) -> Result<SdkTracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(otlp_endpoint)
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("thor").build())
+        .build())
+}
+
+/// `ansi` colors the logs, which is only wanted on a terminal.
+fn log_layer<W>(
+    log_format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let format = tracing_subscriber::fmt::format()
+        .with_file(true)
+        .with_line_number(true)
+        .with_target(false);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match log_format {
+        LogFormat::Json => layer.event_format(format.json()).boxed(),
+        LogFormat::Compact => layer.event_format(format.compact()).boxed(),
+        LogFormat::Pretty => layer.event_format(format.pretty()).boxed(),
+    }
 }
@@ -0,0 +1,89 @@
+use crate::shutdown::ShutdownListener;
+use anyhow::Result;
+use axum::Router;
+use axum::http::StatusCode;
+use axum::routing::get;
+use prometheus::{
+    Encoder, GaugeVec, HistogramVec, IntCounterVec, TextEncoder, register_gauge_vec,
+    register_histogram_vec, register_int_counter_vec,
+};
+use std::sync::LazyLock;
+
+pub static INVOICES_CREATED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "thor_invoices_created_total",
+        "Number of invoices created.",
+        &["username"]
+    )
+    .unwrap()
+});
+
+pub static INVOICE_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "thor_invoice_errors_total",
+        "Number of failed invoice creation attempts.",
+        &["username", "backend"]
+    )
+    .unwrap()
+});
+
+pub static LNURLP_INFO_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "thor_lnurlp_info_requests_total",
+        "Number of lnurlp info requests.",
+        &["username"]
+    )
+    .unwrap()
+});
+
+pub static NWC_ATTEMPT_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "thor_nwc_attempt_duration_seconds",
+        "Duration of a single invoice creation attempt against a backend.",
+        &["backend"]
+    )
+    .unwrap()
+});
+
+/// Percentiles of the invoice creation attempts of each backend, see `BackendLatencies`.
+pub static BACKEND_LATENCY: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "thor_backend_latency_milliseconds",
+        "Percentiles of the duration of invoice creation attempts against a backend.",
+        &["backend", "endpoint", "quantile"]
+    )
+    .unwrap()
+});
+
+/// Serves `/metrics` on its own address, so that it can be firewalled off from the public internet.
+pub async fn run_metrics_server(listen_addr: &str, shutdown: ShutdownListener) -> Result<()> {
+    let app = Router::new().route("/metrics", get(get_metrics));
+
+    let listener = tokio::net::TcpListener::bind(listen_addr).await?;
+    tracing::info!("metrics listening on {}", listen_addr);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.wait())
+        .await?;
+    Ok(())
+}
+
+async fn get_metrics() -> Result<String, StatusCode> {
+    let mut buf = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    String::from_utf8(buf).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn get_metrics_exposes_counters() {
+        INVOICES_CREATED.with_label_values(&["metrics-test"]).inc();
+        let text = get_metrics().await.unwrap();
+        assert!(text.contains(r#"thor_invoices_created_total{username="metrics-test"} 1"#));
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// - [LUD-18: Payer identity in payRequest protocol](https://github.com/lnurl/luds/blob/luds/18.md)
+
+/// The payer data accepted by a user. It is advertised as `payerData` in the lnurlp info.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayerDataConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<PayerDataField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<PayerDataField>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayerDataField {
+    #[serde(default)]
+    pub mandatory: bool,
+}
+
+/// The payer data sent by the wallet along with the invoice request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PayerData {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub pubkey: Option<String>,
+}
+
+impl PayerDataConfig {
+    /// Checks that every mandatory field is present, and no field is sent unless accepted.
+    pub fn validate(&self, payer_data: &PayerData) -> Result<()> {
+        check_field("name", &self.name, &payer_data.name)?;
+        check_field("email", &self.email, &payer_data.email)?;
+        check_field("pubkey", &self.pubkey, &payer_data.pubkey)?;
+        if let Some(pubkey) = &payer_data.pubkey
+            && (pubkey.len() != 64 || !pubkey.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            anyhow::bail!("payerData.pubkey must be a hex encoded 32 bytes public key");
+        }
+        Ok(())
+    }
+}
+
+fn check_field(
+    name: &str,
+    accepted: &Option<PayerDataField>,
+    value: &Option<String>,
+) -> Result<()> {
+    match (accepted, value) {
+        (None, Some(_)) => anyhow::bail!("payerData.{} is not accepted", name),
+        (Some(field), None) if field.mandatory => {
+            anyhow::bail!("payerData.{} is mandatory", name)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> PayerDataConfig {
+        PayerDataConfig {
+            name: Some(PayerDataField { mandatory: true }),
+            email: None,
+            pubkey: Some(PayerDataField { mandatory: false }),
+        }
+    }
+
+    #[test]
+    fn serialize_config() {
+        let v = serde_json::to_value(config()).unwrap();
+        assert_eq!(
+            v,
+            serde_json::json!({"name": {"mandatory": true}, "pubkey": {"mandatory": false}})
+        );
+    }
+
+    #[test]
+    fn validate_payer_data() {
+        let config = config();
+        let ok = PayerData {
+            name: Some("bob".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate(&ok).is_ok());
+
+        let missing_name = PayerData::default();
+        assert!(config.validate(&missing_name).is_err());
+
+        let not_accepted = PayerData {
+            name: Some("bob".to_string()),
+            email: Some("bob@example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate(&not_accepted).is_err());
+
+        let bad_pubkey = PayerData {
+            name: Some("bob".to_string()),
+            pubkey: Some("not a pubkey".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate(&bad_pubkey).is_err());
+    }
+}
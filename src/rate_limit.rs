@@ -0,0 +1,223 @@
+use crate::config::RateLimitConfig;
+use crate::error::HttpError;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultDirectRateLimiter, DefaultKeyedRateLimiter, Quota, RateLimiter};
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub fn quota(config: &RateLimitConfig) -> Quota {
+    // zero values are rejected by `Config::validate`.
+    let requests_per_minute =
+        NonZeroU32::new(config.requests_per_minute).unwrap_or(NonZeroU32::MIN);
+    let burst = NonZeroU32::new(config.burst).unwrap_or(NonZeroU32::MIN);
+    Quota::per_minute(requests_per_minute).allow_burst(burst)
+}
+
+/// Returns how long the caller should wait if the limit is exceeded.
+fn check(limiter: &DefaultDirectRateLimiter) -> Result<(), Duration> {
+    limiter
+        .check()
+        .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+}
+
+/// Rate limits requests per user, by username, so that a reload doesn't give every user a full
+/// burst again. A user's limiter only starts over when its quota changes.
+#[derive(Default)]
+pub struct UserRateLimiters {
+    limiters: DashMap<String, (Quota, Arc<DefaultDirectRateLimiter>)>,
+}
+
+impl UserRateLimiters {
+    /// Returns how long the caller should wait if the limit of `username` is exceeded.
+    pub fn check(&self, username: &str, quota: Quota) -> Result<(), Duration> {
+        let limiter = {
+            let mut entry = self
+                .limiters
+                .entry(username.to_string())
+                .or_insert_with(|| (quota, Arc::new(RateLimiter::direct(quota))));
+            if entry.0 != quota {
+                *entry = (quota, Arc::new(RateLimiter::direct(quota)));
+            }
+            entry.1.clone()
+        };
+        check(&limiter)
+    }
+}
+
+/// Rate limits requests by the client's IP address, across all users.
+pub struct IpRateLimiter {
+    limiter: DefaultKeyedRateLimiter<IpAddr>,
+    trust_proxy: bool,
+}
+
+impl IpRateLimiter {
+    pub fn new(config: &RateLimitConfig, trust_proxy: bool) -> Arc<IpRateLimiter> {
+        let ip_rate_limiter = Arc::new(IpRateLimiter {
+            limiter: RateLimiter::keyed(quota(config)),
+            trust_proxy,
+        });
+
+        // forget the IPs we haven't seen for a while, so that the memory won't grow unboundedly.
+        let weak = Arc::downgrade(&ip_rate_limiter);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(ip_rate_limiter) => {
+                        ip_rate_limiter.limiter.retain_recent();
+                        ip_rate_limiter.limiter.shrink_to_fit();
+                    }
+                    None => break,
+                }
+            }
+        });
+        ip_rate_limiter
+    }
+}
+
+pub async fn ip_rate_limit(
+    State(ip_rate_limiter): State<Arc<IpRateLimiter>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    // warned about on startup, see `serve`.
+    let Some(ip) = request_client_ip(&req, ip_rate_limiter.trust_proxy) else {
+        return next.run(req).await;
+    };
+    if let Err(not_until) = ip_rate_limiter.limiter.check_key(&ip) {
+        tracing::warn!(ip = %ip, "ip rate limit exceeded.");
+        let retry_after = not_until.wait_time_from(DefaultClock::default().now());
+        return HttpError::too_many_requests(retry_after).into_response();
+    }
+    next.run(req).await
+}
+
+/// The IP of the client making the request, put in the request extensions by [`add_client_ip`]
+/// when there is one.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+pub async fn add_client_ip(
+    State(trust_proxy): State<bool>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if let Some(ip) = request_client_ip(&req, trust_proxy) {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(req).await
+}
+
+/// The IP of the client making `req`, see `client_ip`.
+pub fn request_client_ip(req: &Request, trust_proxy: bool) -> Option<IpAddr> {
+    // there is no peer address when listening on a unix socket.
+    let peer_addr = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer_addr)| *peer_addr);
+    client_ip(req.headers(), peer_addr, trust_proxy)
+}
+
+/// The headers can be forged by anyone, so they are only used when we're behind a trusted proxy.
+fn client_ip(
+    headers: &HeaderMap,
+    peer_addr: Option<SocketAddr>,
+    trust_proxy: bool,
+) -> Option<IpAddr> {
+    if trust_proxy {
+        // X-Forwarded-For: <client>, <proxy1>, <proxy2>
+        let forwarded_for = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .and_then(|v| v.trim().parse().ok());
+        if forwarded_for.is_some() {
+            return forwarded_for;
+        }
+        let real_ip = headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok());
+        if real_ip.is_some() {
+            return real_ip;
+        }
+    }
+    peer_addr.map(|peer_addr| peer_addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rejects_requests_over_burst() {
+        let limiter = RateLimiter::direct(quota(&RateLimitConfig {
+            requests_per_minute: 1,
+            burst: 2,
+        }));
+        assert!(check(&limiter).is_ok());
+        assert!(check(&limiter).is_ok());
+        let wait = check(&limiter).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn user_rate_limiters_start_over_only_on_new_quota() {
+        let limiters = UserRateLimiters::default();
+        let one = quota(&RateLimitConfig {
+            requests_per_minute: 1,
+            burst: 1,
+        });
+        assert!(limiters.check("alice", one).is_ok());
+        assert!(limiters.check("alice", one).is_err());
+        assert!(limiters.check("bob", one).is_ok());
+
+        let two = quota(&RateLimitConfig {
+            requests_per_minute: 1,
+            burst: 2,
+        });
+        assert!(limiters.check("alice", two).is_ok());
+        assert!(limiters.check("alice", two).is_ok());
+        assert!(limiters.check("alice", two).is_err());
+    }
+
+    #[test]
+    fn client_ip_ignores_headers_without_trust_proxy() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1".parse().unwrap());
+        let peer_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        assert_eq!(
+            client_ip(&headers, Some(peer_addr), false),
+            Some("127.0.0.1".parse::<IpAddr>().unwrap())
+        );
+        assert_eq!(client_ip(&headers, None, false), None);
+    }
+
+    #[test]
+    fn client_ip_uses_forwarded_headers_with_trust_proxy() {
+        let peer_addr: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.insert("x-real-ip", "3.3.3.3".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, Some(peer_addr), true),
+            Some("1.1.1.1".parse::<IpAddr>().unwrap())
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "3.3.3.3".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, None, true),
+            Some("3.3.3.3".parse::<IpAddr>().unwrap())
+        );
+    }
+}
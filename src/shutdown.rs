@@ -0,0 +1,34 @@
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+
+/// Waits for SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to install SIGTERM handler.");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => tracing::info!("received SIGTERM, shutting down."),
+        _ = tokio::signal::ctrl_c() => tracing::info!("received Ctrl-C, shutting down."),
+    }
+}
+
+/// Lets several servers wait for the same shutdown.
+#[derive(Clone)]
+pub struct ShutdownListener(watch::Receiver<()>);
+
+impl ShutdownListener {
+    pub fn new() -> (watch::Sender<()>, ShutdownListener) {
+        let (tx, rx) = watch::channel(());
+        (tx, ShutdownListener(rx))
+    }
+
+    /// Resolves when shutdown is triggered, or the sender is dropped.
+    pub async fn wait(mut self) {
+        let _ = self.0.changed().await;
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// LUD-09 limits for the human readable fields.
+pub const MAX_MESSAGE_LEN: usize = 144;
+pub const MAX_DESCRIPTION_LEN: usize = 144;
+
+// - [LUD-09: successAction field for payRequest](https://github.com/lnurl/luds/blob/luds/09.md)
+// - [LUD-10: aes success action in payRequest](https://github.com/lnurl/luds/blob/luds/10.md)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "tag", rename_all = "lowercase")]
+pub enum SuccessAction {
+    Message {
+        message: String,
+    },
+    Url {
+        url: String,
+        description: String,
+    },
+    Aes {
+        description: String,
+        ciphertext: String, // base64
+        iv: String,         // base64
+    },
+}
+
+impl SuccessAction {
+    /// Checks the LUD-09 constraints. `domain` is the domain of the callback url,
+    /// which a url success action must share.
+    pub fn validate(&self, domain: &str) -> Result<()> {
+        match self {
+            SuccessAction::Message { message } => {
+                if message.chars().count() > MAX_MESSAGE_LEN {
+                    anyhow::bail!("message too long, at most {} characters", MAX_MESSAGE_LEN);
+                }
+            }
+            SuccessAction::Url { url, description } => {
+                if description.chars().count() > MAX_DESCRIPTION_LEN {
+                    anyhow::bail!(
+                        "description too long, at most {} characters",
+                        MAX_DESCRIPTION_LEN
+                    );
+                }
+                let url = url::Url::parse(url)?;
+                if url.host_str() != Some(domain) {
+                    anyhow::bail!("url must be on the same domain as the callback: {}", domain);
+                }
+            }
+            SuccessAction::Aes { description, .. } => {
+                if description.chars().count() > MAX_DESCRIPTION_LEN {
+                    anyhow::bail!(
+                        "description too long, at most {} characters",
+                        MAX_DESCRIPTION_LEN
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_message() {
+        let action = SuccessAction::Message {
+            message: "thanks".to_string(),
+        };
+        let v = serde_json::to_value(&action).unwrap();
+        assert_eq!(
+            v,
+            serde_json::json!({"tag": "message", "message": "thanks"})
+        );
+    }
+
+    #[test]
+    fn validate_rejects_url_on_other_domain() {
+        let action = SuccessAction::Url {
+            url: "https://evil.com/thanks".to_string(),
+            description: "thanks".to_string(),
+        };
+        assert!(action.validate("example.com").is_err());
+
+        let action = SuccessAction::Url {
+            url: "https://example.com/thanks".to_string(),
+            description: "thanks".to_string(),
+        };
+        assert!(action.validate("example.com").is_ok());
+    }
+}
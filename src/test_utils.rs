@@ -0,0 +1,152 @@
+//! Invoice creators and states for testing the handlers without live backends.
+
+use crate::config::UserConfig;
+use crate::exchange_rate::tests::usd_rates;
+use crate::http_server::{AppState, User};
+use crate::invoice_creator::{BackendInvoiceCreatorFactory, InvoiceCreator};
+use crate::zap::MAX_PENDING_ZAPS;
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// The state of example.com with `users`, keyed by their lowercase name or alias. Everything else
+/// is left to its default, and can be set by the tests of `http_server`.
+pub fn app_state(users: HashMap<String, Arc<User>>) -> AppState {
+    AppState {
+        domain: "example.com".to_string(),
+        use_forwarded_host: false,
+        users: RwLock::new(users),
+        nostr_pubkeys: Default::default(),
+        health_check_timeout: Duration::from_secs(1),
+        health_cache: Default::default(),
+        max_avatar_bytes: 0,
+        backend_stats: Default::default(),
+        backend_latencies: Default::default(),
+        daily_totals: Default::default(),
+        recent_invoices: Default::default(),
+        last_invoices: Default::default(),
+        disposable_links: Default::default(),
+        withdraw_sessions: Default::default(),
+        withdrawn_totals: Default::default(),
+        user_rate_limiters: Default::default(),
+        zap_pollers: Arc::new(Semaphore::new(MAX_PENDING_ZAPS)),
+        disposable_link_ttl: Duration::from_secs(600),
+        catch_all_user: None,
+        list_users: false,
+        exchange_rates: None,
+        invoice_log: None,
+        invoice_creator_factory: Arc::new(BackendInvoiceCreatorFactory),
+        started_at: Instant::now(),
+    }
+}
+
+/// The state of `app_state` with one user, `name`, whose backends are `creators`, and who has
+/// limits low enough for the tests to hit: comments up to 10 characters, 1,000 to 1,000,000 msat,
+/// and a burst of 2 requests. Amounts can be given in USD, at 100,000 USD per BTC.
+pub fn app_state_with_user(name: &str, creators: Vec<Box<dyn InvoiceCreator>>) -> AppState {
+    let user_config: UserConfig = serde_json::from_value(serde_json::json!({
+        "name": name,
+        "comment_allowed": 10,
+        "success_action": {"tag": "message", "message": "thanks"},
+        "min_sendable_msat": 1_000,
+        "max_sendable_msat": 1_000_000,
+        "rate_limit": {"requests_per_minute": 1, "burst": 2},
+        "payer_data": {"name": {"mandatory": false}},
+    }))
+    .unwrap();
+    let creators = creators.into_iter().map(Arc::from).collect();
+    let user = User::with_invoice_creators(&user_config, 0, creators, vec![]).unwrap();
+    AppState {
+        exchange_rates: Some(usd_rates()),
+        ..app_state(HashMap::from([(name.to_string(), Arc::new(user))]))
+    }
+}
+
+/// Returns the queued responses in order, one per `create_invoice` call, and errors once they run out.
+/// It's always healthy.
+pub struct MockInvoiceCreator {
+    responses: Mutex<VecDeque<Result<String>>>,
+}
+
+impl MockInvoiceCreator {
+    pub fn new(responses: Vec<Result<String>>) -> MockInvoiceCreator {
+        MockInvoiceCreator {
+            responses: Mutex::new(responses.into()),
+        }
+    }
+
+    /// Returns `invoice` for the first `n` calls.
+    pub fn repeat(invoice: &str, n: usize) -> MockInvoiceCreator {
+        MockInvoiceCreator::new((0..n).map(|_| Ok(invoice.to_string())).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl InvoiceCreator for MockInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_invoice(
+        &self,
+        _amount_msat: u64,
+        _description: &str,
+        _comment: Option<&str>,
+        _expiry: Option<u64>,
+    ) -> Result<String> {
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Err(anyhow::anyhow!("no more mock responses")))
+    }
+}
+
+/// Fails every call, including health checks.
+pub struct MockFailCreator;
+
+#[async_trait::async_trait]
+impl InvoiceCreator for MockFailCreator {
+    fn backend(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        anyhow::bail!("backend down")
+    }
+
+    async fn create_invoice(
+        &self,
+        _amount_msat: u64,
+        _description: &str,
+        _comment: Option<&str>,
+        _expiry: Option<u64>,
+    ) -> Result<String> {
+        anyhow::bail!("backend down")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mock_returns_responses_in_order() {
+        let creator = MockInvoiceCreator::new(vec![
+            Ok("lnbc1first".to_string()),
+            Err(anyhow::anyhow!("second fails")),
+        ]);
+        assert_eq!(
+            creator.create_invoice(1000, "", None, None).await.unwrap(),
+            "lnbc1first"
+        );
+        assert!(creator.create_invoice(1000, "", None, None).await.is_err());
+        assert!(creator.create_invoice(1000, "", None, None).await.is_err());
+    }
+}
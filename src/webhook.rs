@@ -0,0 +1,113 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Notifies a user's integration, e.g. an e-commerce backend, whenever an invoice is created.
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvoiceCreated {
+    pub username: String,
+    pub amount_msat: u64,
+    pub bolt11: String,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+}
+
+impl InvoiceCreated {
+    pub fn now(username: &str, amount_msat: u64, bolt11: &str) -> InvoiceCreated {
+        InvoiceCreated {
+            username: username.to_string(),
+            amount_msat,
+            bolt11: bolt11.to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+impl Webhook {
+    pub fn new(url: &str, timeout: Duration) -> Result<Webhook> {
+        Ok(Webhook {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url: url.to_string(),
+        })
+    }
+
+    /// POSTs the event, retrying once. Failures are only logged, they never affect the payer.
+    pub async fn notify(self: Arc<Self>, event: InvoiceCreated) {
+        for attempt in 1..=2 {
+            match self.post(&event).await {
+                Ok(()) => return,
+                Err(e) => tracing::warn!(
+                    user = event.username,
+                    url = self.url,
+                    attempt = attempt,
+                    error = %e,
+                    "failed to call webhook."
+                ),
+            }
+        }
+    }
+
+    async fn post(&self, event: &InvoiceCreated) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // fails the first call, so that the retry is exercised.
+    async fn handler(
+        State(calls): State<Arc<AtomicUsize>>,
+        Json(body): Json<serde_json::Value>,
+    ) -> StatusCode {
+        assert_eq!(body["username"], "alice");
+        assert_eq!(body["amount_msat"], 1500);
+        assert_eq!(body["bolt11"], "lnbc1test");
+        match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::OK,
+        }
+    }
+
+    #[tokio::test]
+    async fn notify_retries_once() -> Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/hook", post(handler))
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let webhook = Arc::new(Webhook::new(
+            &format!("http://{}/hook", addr),
+            Duration::from_secs(5),
+        )?);
+        webhook
+            .notify(InvoiceCreated::now("alice", 1500, "lnbc1test"))
+            .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
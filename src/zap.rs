@@ -0,0 +1,287 @@
+use crate::config::ZapConfig;
+use crate::invoice_creator::{InvoiceCreator, InvoiceStatus};
+use ::url::Host;
+use anyhow::Result;
+use nostr_sdk::prelude::*;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the backend is asked whether a zapped invoice has been paid.
+const PAYMENT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait for the relays to accept a zap receipt.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many zapped invoices can be polled at once, each until paid or expired. Zap requests beyond
+/// it are rejected.
+pub const MAX_PENDING_ZAPS: usize = 1_000;
+/// How many of the relays asked by a zap request get its receipt.
+const MAX_REQUESTED_RELAYS: usize = 5;
+
+// - [NIP-57: Lightning Zaps](https://github.com/nostr-protocol/nips/blob/master/57.md)
+/// Signs zap receipts for a user, and publishes them once the zapped invoices are paid.
+pub struct Zapper {
+    keys: Keys,
+    relays: Vec<RelayUrl>,
+}
+
+impl Zapper {
+    pub fn new(config: &ZapConfig) -> Result<Zapper> {
+        let keys = Keys::parse(&config.nostr_secret_key)?;
+        let relays = config
+            .relays
+            .iter()
+            .map(|url| RelayUrl::parse(url))
+            .collect::<Result<_, _>>()?;
+        Ok(Zapper { keys, relays })
+    }
+
+    /// Advertised as `nostrPubkey`, so that clients can verify the zap receipts.
+    pub fn pubkey(&self) -> String {
+        self.keys.public_key().to_hex()
+    }
+
+    /// Waits until `invoice` is paid, then publishes its zap receipt. Gives up when the invoice expires.
+    pub async fn publish_when_paid(
+        self: Arc<Self>,
+        creator: Arc<dyn InvoiceCreator>,
+        invoice: String,
+        zap_request: Event,
+        expiry: Duration,
+    ) {
+        let deadline = Instant::now() + expiry;
+        while Instant::now() < deadline {
+            tokio::time::sleep(PAYMENT_POLL_INTERVAL).await;
+            match creator.lookup_invoice(&invoice).await {
+                Ok(InvoiceStatus::Unpaid) => {}
+                Ok(InvoiceStatus::Paid { preimage }) => {
+                    match self.publish_receipt(&invoice, preimage, &zap_request).await {
+                        Ok(()) => tracing::info!(invoice = invoice, "zap receipt published."),
+                        Err(e) => {
+                            tracing::error!(invoice = invoice, error = %e, "failed to publish zap receipt.")
+                        }
+                    }
+                    return;
+                }
+                Err(e) => {
+                    tracing::warn!(invoice = invoice, error = %e, "failed to look up zapped invoice.")
+                }
+            }
+        }
+        tracing::info!(
+            invoice = invoice,
+            "zapped invoice expired unpaid, no zap receipt published."
+        );
+    }
+
+    async fn publish_receipt(
+        &self,
+        invoice: &str,
+        preimage: Option<String>,
+        zap_request: &Event,
+    ) -> Result<()> {
+        let receipt =
+            EventBuilder::zap_receipt(invoice, preimage, zap_request).sign_with_keys(&self.keys)?;
+
+        // the receipt goes to the public relays asked by the zapper, as well as our own.
+        let client = Client::default();
+        for relay in self
+            .relays
+            .iter()
+            .cloned()
+            .chain(requested_relays(zap_request))
+        {
+            client.add_relay(relay).await?;
+        }
+        client.connect().await;
+        client.wait_for_connection(PUBLISH_TIMEOUT).await;
+        let res = client.send_event(&receipt).await;
+        client.disconnect().await;
+        let output = res?;
+        if output.success.is_empty() {
+            anyhow::bail!("no relay accepted the zap receipt: {:?}", output.failed);
+        }
+        Ok(())
+    }
+}
+
+/// Parses and checks the zap request sent in the `nostr` query parameter, as required by NIP-57.
+pub fn parse_zap_request(json: &str, amount_msat: u64) -> Result<Event> {
+    let event = Event::from_json(json)?;
+    event.verify()?;
+    if event.kind != Kind::ZapRequest {
+        anyhow::bail!("kind must be {}", Kind::ZapRequest.as_u16());
+    }
+    let count = |kind: TagKind| event.tags.iter().filter(|tag| tag.kind() == kind).count();
+    if count(TagKind::p()) != 1 {
+        anyhow::bail!("must have exactly one p tag");
+    }
+    if count(TagKind::e()) > 1 {
+        anyhow::bail!("must have at most one e tag");
+    }
+    if requested_relays(&event).next().is_none() {
+        anyhow::bail!("must have a relays tag with a public wss relay");
+    }
+    if let Some(amount) = event
+        .tags
+        .iter()
+        .find(|tag| tag.kind() == TagKind::Amount)
+        .and_then(|tag| tag.content())
+        && amount.parse::<u64>().ok() != Some(amount_msat)
+    {
+        anyhow::bail!("amount tag must be the same as amount");
+    }
+    Ok(event)
+}
+
+// invalid relay urls are ignored, they won't accept the receipt anyway. So are the ones which
+// aren't public, since anyone can make us connect to them.
+fn requested_relays(zap_request: &Event) -> impl Iterator<Item = RelayUrl> + '_ {
+    zap_request
+        .tags
+        .iter()
+        .filter(|tag| tag.kind() == TagKind::Relays)
+        .flat_map(|tag| tag.as_slice().iter().skip(1))
+        .filter(|url| is_public_relay(url))
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .take(MAX_REQUESTED_RELAYS)
+}
+
+/// Only `wss` relays, not on loopback, private or link-local addresses. Host names aren't resolved,
+/// apart from `localhost`.
+fn is_public_relay(url: &str) -> bool {
+    let Ok(url) = ::url::Url::parse(url) else {
+        return false;
+    };
+    if url.scheme() != "wss" {
+        return false;
+    }
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+        Some(Host::Ipv4(ip)) => is_public_ipv4(ip),
+        Some(Host::Ipv6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => {
+                let first = ip.segments()[0];
+                !ip.is_loopback()
+                    && !ip.is_unspecified()
+                    && first & 0xfe00 != 0xfc00 // unique local
+                    && first & 0xffc0 != 0xfe80 // link-local
+            }
+        },
+        None => false,
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    !ip.is_loopback()
+        && !ip.is_private()
+        && !ip.is_link_local()
+        && !ip.is_unspecified()
+        && !ip.is_broadcast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zap_request(keys: &Keys, tags: Vec<Tag>) -> String {
+        EventBuilder::new(Kind::ZapRequest, "")
+            .tags(tags)
+            .sign_with_keys(keys)
+            .unwrap()
+            .as_json()
+    }
+
+    fn valid_tags() -> Vec<Tag> {
+        // the recipient, which can't be the zapper itself.
+        vec![
+            Tag::public_key(Keys::generate().public_key()),
+            Tag::parse(["relays", "wss://relay.damus.io"]).unwrap(),
+            Tag::parse(["amount", "21000"]).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn accepts_valid_zap_request() {
+        let keys = Keys::generate();
+        let json = zap_request(&keys, valid_tags());
+        assert!(parse_zap_request(&json, 21_000).is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatched_amount() {
+        let keys = Keys::generate();
+        let json = zap_request(&keys, valid_tags());
+        assert!(parse_zap_request(&json, 1_000).is_err());
+    }
+
+    #[test]
+    fn rejects_zap_request_without_p_tag() {
+        let keys = Keys::generate();
+        let tags = valid_tags().into_iter().skip(1).collect();
+        let json = zap_request(&keys, tags);
+        assert!(parse_zap_request(&json, 21_000).is_err());
+    }
+
+    #[test]
+    fn requested_relays_are_public_wss_only() {
+        let keys = Keys::generate();
+        let relays = Tag::parse([
+            "relays",
+            "wss://relay.damus.io",
+            "ws://relay.example.com",
+            "wss://localhost:7000",
+            "wss://127.0.0.1",
+            "wss://10.0.0.1",
+            "wss://169.254.169.254",
+            "wss://[::1]",
+            "wss://[fd00::1]",
+            "wss://[::ffff:192.168.1.1]",
+            "wss://nos.lol",
+        ])
+        .unwrap();
+        let json = zap_request(
+            &keys,
+            vec![Tag::public_key(Keys::generate().public_key()), relays],
+        );
+        let event = parse_zap_request(&json, 21_000).unwrap();
+        let relays: Vec<_> = requested_relays(&event)
+            .map(|url| url.to_string())
+            .collect();
+        assert_eq!(relays, ["wss://relay.damus.io", "wss://nos.lol"]);
+    }
+
+    #[test]
+    fn requested_relays_are_capped() {
+        let keys = Keys::generate();
+        let urls = (0..10).map(|i| format!("wss://relay{}.example.com", i));
+        let relays = Tag::parse(std::iter::once("relays".to_string()).chain(urls)).unwrap();
+        let json = zap_request(
+            &keys,
+            vec![Tag::public_key(Keys::generate().public_key()), relays],
+        );
+        let event = parse_zap_request(&json, 21_000).unwrap();
+        assert_eq!(requested_relays(&event).count(), MAX_REQUESTED_RELAYS);
+    }
+
+    #[test]
+    fn rejects_zap_request_without_public_relay() {
+        let keys = Keys::generate();
+        let tags = vec![
+            Tag::public_key(Keys::generate().public_key()),
+            Tag::parse(["relays", "wss://127.0.0.1:7000"]).unwrap(),
+        ];
+        let json = zap_request(&keys, tags);
+        assert!(parse_zap_request(&json, 21_000).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_zap_request() {
+        let keys = Keys::generate();
+        let json = zap_request(&keys, valid_tags()).replace("21000", "42000");
+        assert!(parse_zap_request(&json, 42_000).is_err());
+    }
+}
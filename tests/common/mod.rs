@@ -0,0 +1,91 @@
+//! A fake NWC wallet service answering over a relay on loopback, shared by the integration tests
+//! and the benchmarks.
+
+use anyhow::Result;
+use nostr_relay_builder::builder::RateLimit;
+use nostr_relay_builder::{LocalRelay, RelayBuilder};
+use nostr_sdk::prelude::*;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// A NWC wallet service, which answers every `make_invoice` request with `invoice`, or with an
+/// error if there is none. It remembers the params of the requests.
+pub struct FakeWallet {
+    keys: Keys,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl FakeWallet {
+    pub async fn start(relay_url: &str, invoice: Option<&str>) -> Result<FakeWallet> {
+        let keys = Keys::generate();
+        let client = Client::new(keys.clone());
+        client.add_relay(relay_url).await?;
+        client.connect().await;
+        let filter = Filter::new()
+            .kind(Kind::WalletConnectRequest)
+            .pubkey(keys.public_key());
+        client.subscribe(filter, None).await?;
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let mut notifications = client.notifications();
+        let (wallet, recorded, invoice) =
+            (keys.clone(), requests.clone(), invoice.map(String::from));
+        tokio::spawn(async move {
+            while let Ok(notification) = notifications.recv().await {
+                let RelayPoolNotification::Event { event, .. } = notification else {
+                    continue;
+                };
+                let request =
+                    nip04::decrypt(wallet.secret_key(), &event.pubkey, &event.content).unwrap();
+                let request: Value = serde_json::from_str(&request).unwrap();
+                recorded.lock().unwrap().push(request["params"].clone());
+                let response = match &invoice {
+                    Some(invoice) => serde_json::json!({
+                        "result_type": "make_invoice",
+                        "result": {"invoice": invoice, "payment_hash": ""},
+                    }),
+                    None => serde_json::json!({
+                        "result_type": "make_invoice",
+                        "error": {"code": "INTERNAL", "message": "wallet is down"},
+                    }),
+                };
+                let content =
+                    nip04::encrypt(wallet.secret_key(), &event.pubkey, response.to_string())
+                        .unwrap();
+                let reply = EventBuilder::new(Kind::WalletConnectResponse, content)
+                    .tag(Tag::event(event.id))
+                    .tag(Tag::public_key(event.pubkey))
+                    .sign_with_keys(&wallet)
+                    .unwrap();
+                client.send_event(&reply).await.unwrap();
+            }
+        });
+        Ok(FakeWallet { keys, requests })
+    }
+
+    /// A NWC URI connecting to this wallet, as a TOML string.
+    pub fn uri(&self, relay_url: &str) -> Result<String> {
+        let uri = NostrWalletConnectURI::new(
+            self.keys.public_key(),
+            vec![RelayUrl::parse(relay_url)?],
+            Keys::generate().secret_key().clone(),
+            None,
+        );
+        Ok(format!("{:?}", uri.to_string()))
+    }
+
+    // the benchmarks don't look at them.
+    #[allow(dead_code)]
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+pub async fn start_relay() -> Result<LocalRelay> {
+    // one connection carries all the requests of a wallet, way over the default rate limit.
+    let rate_limit = RateLimit {
+        max_reqs: 500,
+        notes_per_minute: u32::MAX,
+    };
+    Ok(LocalRelay::run(RelayBuilder::default().rate_limit(rate_limit)).await?)
+}
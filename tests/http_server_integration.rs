@@ -0,0 +1,103 @@
+//! The whole `create_invoice` flow against real NWC backends: thor's HTTP server, the NWC clients,
+//! and fake wallet services answering over a relay on loopback.
+
+mod common;
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use bitcoin_hashes::Sha256;
+use common::{FakeWallet, start_relay};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::sync::Arc;
+use thor::config::{Config, ConfigFormat};
+use thor::http_server::{AppState, router};
+use thor::invoice_creator::BackendInvoiceCreatorFactory;
+
+/// Serves alice, with `nwcs` as her backends, and returns the base URL.
+async fn start_server(nwcs: &str) -> Result<String> {
+    let config = format!(
+        r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:0"
+log_dir = "/tmp/thor"
+
+[[users]]
+name = "alice"
+nwcs = [{nwcs}]
+retry_policy = {{ nwc_retry_attempts = 2, initial_backoff_ms = 10, max_backoff_ms = 10 }}
+"#
+    );
+    let config = Config::parse(&config, ConfigFormat::Toml)?;
+    let state = AppState::new(&config, Arc::new(BackendInvoiceCreatorFactory))?;
+    let app = router(Arc::new(ArcSwap::from_pointee(state)));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(base_url)
+}
+
+async fn get(url: &str) -> Result<(StatusCode, Value)> {
+    let res = reqwest::get(url).await?;
+    Ok((res.status(), res.json().await?))
+}
+
+#[tokio::test]
+async fn invoice_comes_from_wallet() -> Result<()> {
+    let relay = start_relay().await?;
+    let wallet = FakeWallet::start(&relay.url(), Some("lnbc15n1wallet")).await?;
+    let base_url = start_server(&wallet.uri(&relay.url())?).await?;
+
+    let (status, body) = get(&format!("{base_url}/lnurlp/alice?amount=1500")).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["pr"], "lnbc15n1wallet");
+
+    let requests = wallet.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["amount"], 1500);
+    Ok(())
+}
+
+#[tokio::test]
+async fn wallet_gets_hash_of_metadata() -> Result<()> {
+    let relay = start_relay().await?;
+    let wallet = FakeWallet::start(&relay.url(), Some("lnbc15n1wallet")).await?;
+    let base_url = start_server(&wallet.uri(&relay.url())?).await?;
+
+    let (_, info) = get(&format!("{base_url}/.well-known/lnurlp/alice")).await?;
+    let metadata = info["metadata"].as_str().unwrap();
+    let (status, _) = get(&format!("{base_url}/lnurlp/alice?amount=1500")).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let requests = wallet.requests();
+    assert_eq!(
+        requests[0]["description_hash"],
+        Sha256::hash(metadata.as_bytes()).to_string()
+    );
+    assert!(requests[0]["description"].is_null());
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_wallet_falls_back_to_next() -> Result<()> {
+    let relay = start_relay().await?;
+    let failing = FakeWallet::start(&relay.url(), None).await?;
+    let working = FakeWallet::start(&relay.url(), Some("lnbc15n1fallback")).await?;
+    // primaries are tried in random order, the fallback always comes after.
+    let nwcs = format!(
+        "{}, {{ uri = {}, role = \"fallback\" }}",
+        failing.uri(&relay.url())?,
+        working.uri(&relay.url())?
+    );
+    let base_url = start_server(&nwcs).await?;
+
+    let (status, body) = get(&format!("{base_url}/lnurlp/alice?amount=1500")).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["pr"], "lnbc15n1fallback");
+    // retried as the retry policy says, before moving on.
+    assert_eq!(failing.requests().len(), 2);
+    assert_eq!(working.requests().len(), 1);
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use bitcoin_hashes::Sha256;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use thor::config::{Config, ConfigFormat, UserConfig};
+use thor::error::Lud06Error;
+use thor::http_server::{AppState, router};
+use thor::invoice_creator::{InvoiceCreator, InvoiceCreatorFactory, UserInvoiceCreators};
+
+const INVOICE: &str = "lnbc15n1mockinvoice";
+
+const CONFIG: &str = r#"
+[server]
+domain = "example.com"
+listen_addr = "127.0.0.1:0"
+log_dir = "/tmp/thor"
+
+[[users]]
+name = "alice"
+nwcs = ["nwc://unused"]
+min_sendable_msat = 1000
+max_sendable_msat = 500000
+"#;
+
+/// Returns a canned invoice, and remembers the description it was asked to hash.
+#[derive(Default)]
+struct MockInvoiceCreator {
+    descriptions: Mutex<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+impl InvoiceCreator for MockInvoiceCreator {
+    fn backend(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn check_health(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_invoice(
+        &self,
+        _amount_msat: u64,
+        description: &str,
+        _comment: Option<&str>,
+        _expiry: Option<u64>,
+    ) -> Result<String> {
+        self.descriptions
+            .lock()
+            .unwrap()
+            .push(description.to_string());
+        Ok(INVOICE.to_string())
+    }
+}
+
+/// Gives every user the same mock backend, instead of connecting to the configured ones.
+struct TestInvoiceCreatorFactory {
+    creator: Arc<MockInvoiceCreator>,
+}
+
+impl InvoiceCreatorFactory for TestInvoiceCreatorFactory {
+    fn create_from_config(&self, _user_config: &UserConfig) -> Result<UserInvoiceCreators> {
+        Ok(UserInvoiceCreators {
+            primary: vec![self.creator.clone()],
+            fallback: vec![],
+        })
+    }
+}
+
+struct TestServer {
+    base_url: String,
+    creator: Arc<MockInvoiceCreator>,
+}
+
+impl TestServer {
+    async fn start() -> Result<TestServer> {
+        let config = Config::parse(CONFIG, ConfigFormat::Toml)?;
+        let creator = Arc::new(MockInvoiceCreator::default());
+        let factory = TestInvoiceCreatorFactory {
+            creator: creator.clone(),
+        };
+        let state = AppState::new(&config, Arc::new(factory))?;
+        let app = router(Arc::new(ArcSwap::from_pointee(state)));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(TestServer { base_url, creator })
+    }
+
+    async fn get(&self, path: &str) -> Result<(StatusCode, Value)> {
+        let res = reqwest::get(format!("{}{}", self.base_url, path)).await?;
+        Ok((res.status(), res.json().await?))
+    }
+
+    /// Like `get`, for requests expected to fail with a LUD-06 error.
+    async fn get_error(&self, path: &str) -> Result<(StatusCode, Lud06Error)> {
+        let res = reqwest::get(format!("{}{}", self.base_url, path)).await?;
+        let status = res.status();
+        let e: Lud06Error = res.json().await?;
+        assert_eq!(e.status(), "ERROR");
+        Ok((status, e))
+    }
+}
+
+#[tokio::test]
+async fn unknown_user_is_not_found() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, e) = server.get_error("/.well-known/lnurlp/bob").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(e.reason(), "user bob not found");
+    assert_eq!(e.code(), Some(1001));
+
+    let (status, e) = server.get_error("/lnurlp/bob?amount=1000").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(e.reason(), "user bob not found");
+    Ok(())
+}
+
+#[tokio::test]
+async fn pay_info_has_sendable_range() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, body) = server.get("/.well-known/lnurlp/alice").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["tag"], "payRequest");
+    assert_eq!(body["callback"], "https://example.com/lnurlp/alice");
+    assert_eq!(body["minSendable"], 1000);
+    assert_eq!(body["maxSendable"], 500000);
+    Ok(())
+}
+
+#[tokio::test]
+async fn address_qr_code() -> Result<()> {
+    let server = TestServer::start().await?;
+    let res = reqwest::get(format!("{}/.well-known/lnurlp/alice.png", server.base_url)).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert!(res.bytes().await?.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    let (status, e) = server.get_error("/.well-known/lnurlp/bob.png").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(e.reason(), "user bob not found");
+    Ok(())
+}
+
+#[tokio::test]
+async fn zero_amount_is_rejected() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, e) = server.get_error("/lnurlp/alice?amount=0").await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(e.reason(), "amount must > 0");
+    assert!(server.creator.descriptions.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_amount_is_rejected() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, e) = server.get_error("/lnurlp/alice").await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(e.reason(), "amount is required, in msat");
+    assert_eq!(e.code(), Some(1009));
+    Ok(())
+}
+
+#[tokio::test]
+async fn out_of_range_amount_is_rejected() -> Result<()> {
+    let server = TestServer::start().await?;
+    for amount in [999, 500001, u64::MAX] {
+        let (status, e) = server
+            .get_error(&format!("/lnurlp/alice?amount={}", amount))
+            .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(e.reason(), "amount must be between 1000 and 500000 msat");
+    }
+    assert!(server.creator.descriptions.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn invoice_is_created() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, body) = server.get("/lnurlp/alice?amount=1500").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["pr"], INVOICE);
+    assert_eq!(body["routes"], serde_json::json!([]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn description_hash_commits_to_metadata() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (_, info) = server.get("/.well-known/lnurlp/alice").await?;
+    let metadata = info["metadata"].as_str().unwrap();
+    server.get("/lnurlp/alice?amount=1500").await?;
+
+    // LUD-06: the wallet checks that the invoice's description_hash is sha256(metadata).
+    let descriptions = server.creator.descriptions.lock().unwrap();
+    assert_eq!(descriptions.len(), 1);
+    assert_eq!(
+        Sha256::hash(descriptions[0].as_bytes()),
+        Sha256::hash(metadata.as_bytes())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn routes_are_always_empty() -> Result<()> {
+    let server = TestServer::start().await?;
+    for amount in [1000, 250000, 500000] {
+        let (status, body) = server
+            .get(&format!("/lnurlp/alice?amount={}", amount))
+            .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["routes"], serde_json::json!([]));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn lnurl_encodes_pay_endpoint() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, body) = server.get("/lnurl/alice").await?;
+    assert_eq!(status, StatusCode::OK);
+    let lnurl = body["lnurl"].as_str().unwrap();
+    let (hrp, url) = bech32::decode(lnurl)?;
+    assert_eq!(hrp.as_str(), "lnurl");
+    assert_eq!(url, b"https://example.com/.well-known/lnurlp/alice");
+
+    let res = reqwest::get(format!("{}/.well-known/lnurlp/alice/qr", server.base_url)).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    Ok(())
+}
+
+#[tokio::test]
+async fn disposable_link_is_paid_once() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, body) = server.get("/.well-known/lnurlp/alice/once").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["disposable"], true);
+    let callback = body["callback"].as_str().unwrap();
+    let path = callback.strip_prefix("https://example.com").unwrap();
+    assert!(path.starts_with("/lnurlp/once/"));
+
+    let (status, body) = server.get(&format!("{}?amount=1000", path)).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["pr"].as_str().is_some());
+
+    let (status, e) = server.get_error(&format!("{}?amount=1000", path)).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(e.reason(), "link expired or already used");
+    Ok(())
+}
+
+#[tokio::test]
+async fn invoice_page() -> Result<()> {
+    let server = TestServer::start().await?;
+    let url = format!("{}/lnurlp/alice/invoice?amount=1500", server.base_url);
+    let res = reqwest::get(&url).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(
+        res.headers()["content-type"]
+            .to_str()?
+            .starts_with("text/html")
+    );
+    let page = res.text().await?;
+    assert!(page.contains(INVOICE));
+    assert!(page.contains("data:image/png;base64,"));
+
+    let res = reqwest::Client::new()
+        .get(&url)
+        .header("accept", "application/json")
+        .send()
+        .await?;
+    let body: Value = res.json().await?;
+    assert_eq!(body["pr"], INVOICE);
+
+    let (status, e) = server.get_error("/lnurlp/alice/invoice").await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(e.reason(), "amount is required, in msat");
+    Ok(())
+}
+
+#[tokio::test]
+async fn server_info() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, body) = server.get("/info").await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(body["domain"], "example.com");
+    assert_eq!(body["user_count"], 1);
+    // just started, even on a slow machine.
+    assert!(body["uptime_seconds"].as_u64().unwrap() < 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn usernames_are_not_listed_by_default() -> Result<()> {
+    let server = TestServer::start().await?;
+    let (status, e) = server.get_error("/lnurlp").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(e.reason(), "listing users is disabled");
+    Ok(())
+}